@@ -1,8 +1,20 @@
 use error_stack::ResultExt;
-use sqlx::{sqlite::SqliteConnectOptions, Pool, Row, Sqlite};
-use std::pin::Pin;
+use sqlx::{
+    pool::PoolConnection,
+    query::Query,
+    sqlite::{SqliteArguments, SqliteConnectOptions, SqliteRow},
+    Pool, Row, Sqlite,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+};
+use tokio::sync::Mutex;
 
-use moon_class::{err, def::AsClassManager};
+use moon_class::{
+    def::{AsClassManager, Edge, Pattern, Step},
+    err, util,
+};
 
 const CLASS_INIT_SQL: &str = "-- class_t definition
 
@@ -15,22 +27,137 @@ CREATE TABLE class_t (
 
 CREATE INDEX class_t_class_source ON class_t (class, source);
 CREATE INDEX class_t_target_IDX ON class_t (target,class);
-CREATE INDEX class_t_class_source_target ON class_t (class, source, target);";
+CREATE INDEX class_t_class_source_target ON class_t (class, source, target);
+CREATE INDEX IF NOT EXISTS class_t_source_class ON class_t (source, class);
+
+-- object_t definition, the sources declared objects
+
+CREATE TABLE IF NOT EXISTS object_t (
+    source varchar(500) PRIMARY KEY
+);";
+
+/// `"a""b"`, an identifier quoted for SQL.
+fn quote_identifier(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// `'a''b'`, a string literal quoted for SQL.
+fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
 
 pub struct SqliteClassManager {
     pool: Pool<Sqlite>,
+    /// Connection of the running transaction and its depth, queries go through it.
+    tx: Mutex<Option<(PoolConnection<Sqlite>, usize)>>,
+    /// Names of the unique indexes making classes sets, read once instead of on every append.
+    set_index_set: Mutex<Option<HashSet<String>>>,
 }
 
 impl SqliteClassManager {
     pub fn new(pool: Pool<Sqlite>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            tx: Mutex::new(None),
+            set_index_set: Mutex::new(None),
+        }
     }
 
     pub async fn new_with_file(uri: &str) -> Self {
         let pool = sqlx::SqlitePool::connect_with(SqliteConnectOptions::new().filename(uri))
             .await
             .unwrap();
-        Self { pool }
+        Self::new(pool)
+    }
+
+    /// Makes `class` a set with a unique index, appending a target it already has is ignored.
+    ///
+    /// Fails if the class already has duplicates.
+    pub async fn mark_set(&self, class: &str) -> err::Result<()> {
+        // DDL takes no parameters, the class is quoted into it.
+        if class.contains('\0') {
+            return Err(err::Error::RuntimeError)
+                .attach_printable_lazy(|| format!("mark_set: {class:?} holds a NUL"));
+        }
+
+        let index = format!("class_t_set_{class}");
+        let sql = format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON class_t (class, source, target) WHERE class = {}",
+            quote_identifier(&index),
+            quote_literal(class)
+        );
+
+        self.execute(sqlx::query(&sql))
+            .await
+            .attach_printable_lazy(|| format!("mark_set: {class}"))?;
+
+        self.declare_set(index).await;
+
+        Ok(())
+    }
+
+    pub async fn is_set(&self, class: &str) -> err::Result<bool> {
+        let mut set_index_set = self.set_index_set.lock().await;
+
+        // A database made elsewhere may already have sets.
+        if set_index_set.is_none() {
+            let row_v = self
+                .fetch_all(sqlx::query(
+                    "SELECT name FROM sqlite_master WHERE type='index' AND name LIKE 'class_t_set%'",
+                ))
+                .await?;
+
+            *set_index_set = Some(row_v.iter().map(|row| row.get(0)).collect());
+        }
+
+        let set = set_index_set.as_ref().unwrap();
+
+        Ok(set.contains("class_t_set") || set.contains(&format!("class_t_set_{class}")))
+    }
+
+    /// Makes every class a set.
+    pub async fn mark_all_sets(&self) -> err::Result<()> {
+        self.execute(sqlx::query(
+            "CREATE UNIQUE INDEX IF NOT EXISTS class_t_set ON class_t (class, source, target)",
+        ))
+        .await
+        .attach_printable("mark_all_sets")?;
+
+        self.declare_set("class_t_set".to_string()).await;
+
+        Ok(())
+    }
+
+    /// Adds `index` to the cached set indexes, if they have been read.
+    async fn declare_set(&self, index: String) {
+        if let Some(set) = self.set_index_set.lock().await.as_mut() {
+            set.insert(index);
+        }
+    }
+
+    async fn execute<'q>(&self, query: Query<'q, Sqlite, SqliteArguments<'q>>) -> err::Result<()> {
+        let mut tx = self.tx.lock().await;
+
+        match tx.as_mut() {
+            Some((conn, _)) => query.execute(&mut **conn).await,
+            None => query.execute(&self.pool).await,
+        }
+        .change_context(moon_class::err::Error::RuntimeError)?;
+
+        Ok(())
+    }
+
+    async fn fetch_all<'q>(
+        &self,
+        query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> err::Result<Vec<SqliteRow>> {
+        let mut tx = self.tx.lock().await;
+
+        match tx.as_mut() {
+            Some((conn, _)) => query.fetch_all(&mut **conn).await,
+            None => query.fetch_all(&self.pool).await,
+        }
+        .change_context(moon_class::err::Error::RuntimeError)
     }
 
     pub async fn init(&self) {
@@ -38,36 +165,249 @@ impl SqliteClassManager {
             .execute(&self.pool)
             .await
             .unwrap();
+
+        // A new table has no sets yet.
+        *self.set_index_set.lock().await = Some(HashSet::new());
     }
 
-    pub fn get_source<'a, 'a1, 'a2, 'f>(
-        &'a self,
-        target: &'a1 str,
-        class: &'a2 str,
-    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    /// Rewrites the targets of `class(source)` from the position `edit` returns, in a transaction.
+    ///
+    /// Targets before it keep their rows, the rewritten ones get new ids to keep the order.
+    async fn edit_tail<F>(&mut self, class: &str, source: &str, edit: F) -> err::Result<()>
     where
-        'a: 'f,
-        'a1: 'f,
-        'a2: 'f,
+        F: FnOnce(Vec<String>) -> err::Result<(usize, Vec<String>)>,
     {
-        Box::pin(async move {
-            let rs = sqlx::query(&format!(
-                "SELECT source FROM class_t WHERE target=? AND class=? ORDER BY id"
+        self.begin().await?;
+
+        let rs: err::Result<()> = async {
+            let row_v = self
+                .fetch_all(
+                    sqlx::query(
+                        "SELECT id, target FROM class_t WHERE class=? AND source=? ORDER BY id",
+                    )
+                    .bind(class)
+                    .bind(source),
+                )
+                .await?;
+
+            let (start, tail_v) = edit(row_v.iter().map(|row| row.get(1)).collect())?;
+
+            for row in &row_v[start..] {
+                self.execute(
+                    sqlx::query("DELETE FROM class_t WHERE id=?").bind(row.get::<i64, _>(0)),
+                )
+                .await?;
+            }
+
+            self.append(class, source, tail_v).await
+        }
+        .await;
+
+        match rs {
+            Ok(()) => self.commit().await,
+            Err(e) => {
+                if let Err(rollback_e) = self.rollback().await {
+                    log::error!("edit_tail: rollback failed: {rollback_e:?}");
+                }
+
+                Err(e)
+            }
+        }
+    }
+
+    /// References `(class, source, target)` to objects without fields, deleted ones included.
+    pub async fn dangling_v(&self) -> err::Result<Vec<Edge>> {
+        let row_v = self
+            .fetch_all(sqlx::query(
+                "SELECT class, source, target FROM class_t a WHERE NOT EXISTS (SELECT 1 FROM class_t b WHERE b.source = a.target) ORDER BY id",
             ))
-            .bind(target)
-            .bind(class)
-            .fetch_all(&self.pool)
-            .await
-            .change_context(moon_class::err::Error::RuntimeError)?;
+            .await?;
 
-            let mut arr = vec![];
+        Ok(row_v
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .filter(|(_, _, target): &(String, String, String)| util::is_object_id(target))
+            .collect())
+    }
 
-            for row in rs {
-                arr.push(row.get(0));
+    async fn object_set(&self) -> err::Result<HashSet<String>> {
+        Ok(self
+            .fetch_all(sqlx::query("SELECT source FROM object_t"))
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    async fn mark_from(&self, root_v: Vec<String>) -> err::Result<Vec<String>> {
+        let row_v = self
+            .fetch_all(sqlx::query("SELECT source, target FROM class_t"))
+            .await?;
+        let object_set = self.object_set().await?;
+
+        let mut target_mp: HashMap<String, Vec<String>> = HashMap::new();
+
+        for row in &row_v {
+            target_mp.entry(row.get(0)).or_default().push(row.get(1));
+        }
+
+        let mut live_set = HashSet::new();
+        let mut pending_v = root_v;
+
+        pending_v.extend(
+            target_mp
+                .keys()
+                .filter(|source| !object_set.contains(*source))
+                .cloned(),
+        );
+
+        while let Some(source) = pending_v.pop() {
+            if live_set.contains(&source) {
+                continue;
             }
 
-            Ok(arr)
-        })
+            if let Some(target_v) = target_mp.get(&source) {
+                pending_v.extend(target_v.iter().cloned());
+            }
+
+            live_set.insert(source);
+        }
+
+        Ok(live_set.into_iter().collect())
+    }
+
+    async fn sweep_unmarked(&mut self, live_v: Vec<String>) -> err::Result<Vec<String>> {
+        let live_set = live_v.into_iter().collect::<HashSet<String>>();
+
+        let mut dead_v = self
+            .object_set()
+            .await?
+            .into_iter()
+            .filter(|source| !live_set.contains(source))
+            .collect::<Vec<String>>();
+
+        dead_v.sort();
+
+        let mut garbage_v = vec![];
+
+        for source in dead_v {
+            let row_v = self
+                .fetch_all(
+                    sqlx::query("SELECT 1 FROM class_t WHERE source=? LIMIT 1").bind(&source),
+                )
+                .await?;
+
+            if !row_v.is_empty() {
+                self.execute(sqlx::query("DELETE FROM class_t WHERE source=?").bind(&source))
+                    .await?;
+
+                garbage_v.push(source.clone());
+            }
+
+            // Forgotten once unreachable, fields or not.
+            self.execute(sqlx::query("DELETE FROM object_t WHERE source=?").bind(&source))
+                .await?;
+        }
+
+        Ok(garbage_v)
+    }
+
+    async fn delete_object(&mut self, source: &str, is_cascade: bool) -> err::Result<Vec<String>> {
+        let mut deleted_v = vec![];
+        let mut pending_v = vec![source.to_string()];
+
+        while let Some(source) = pending_v.pop() {
+            if deleted_v.contains(&source) {
+                continue;
+            }
+
+            let child_v = self
+                .fetch_all(sqlx::query("SELECT target FROM class_t WHERE source=?").bind(&source))
+                .await?
+                .iter()
+                .map(|row| row.get(0))
+                .collect::<Vec<String>>();
+
+            // Two deletes, each through its own index.
+            self.execute(sqlx::query("DELETE FROM class_t WHERE source=?").bind(&source))
+                .await?;
+            self.execute(sqlx::query("DELETE FROM class_t WHERE target=?").bind(&source))
+                .await?;
+            self.execute(sqlx::query("DELETE FROM object_t WHERE source=?").bind(&source))
+                .await?;
+
+            if is_cascade {
+                for child in child_v {
+                    // Owned exclusively: an object no one references any more.
+                    let row_v = self
+                        .fetch_all(
+                            sqlx::query(
+                                "SELECT EXISTS (SELECT 1 FROM class_t WHERE source=?), EXISTS (SELECT 1 FROM class_t WHERE target=?)",
+                            )
+                            .bind(&child)
+                            .bind(&child),
+                        )
+                        .await?;
+
+                    if row_v[0].get::<i64, _>(0) == 1 && row_v[0].get::<i64, _>(1) == 0 {
+                        pending_v.push(child);
+                    }
+                }
+            }
+
+            deleted_v.push(source);
+        }
+
+        Ok(deleted_v)
+    }
+
+    /// Commits or rolls back the innermost transaction.
+    async fn end(&self, is_commit: bool) -> err::Result<()> {
+        let mut tx = self.tx.lock().await;
+
+        let (conn, depth) = tx
+            .as_mut()
+            .ok_or(moon_class::err::Error::RuntimeError)
+            .attach_printable("end: no transaction")?;
+
+        let sql_v = if *depth > 1 {
+            let savepoint = format!("sp_{}", *depth - 1);
+
+            if is_commit {
+                vec![format!("RELEASE SAVEPOINT {savepoint}")]
+            } else {
+                vec![
+                    format!("ROLLBACK TO SAVEPOINT {savepoint}"),
+                    format!("RELEASE SAVEPOINT {savepoint}"),
+                ]
+            }
+        } else if is_commit {
+            vec!["COMMIT".to_string()]
+        } else {
+            vec!["ROLLBACK".to_string()]
+        };
+
+        for sql in &sql_v {
+            sqlx::query(sql)
+                .execute(&mut **conn)
+                .await
+                .change_context(moon_class::err::Error::RuntimeError)?;
+        }
+
+        *depth -= 1;
+
+        if *depth == 0 {
+            *tx = None;
+        }
+
+        drop(tx);
+
+        // Sets marked in the transaction may be gone, they are read again.
+        if !is_commit {
+            *self.set_index_set.lock().await = None;
+        }
+
+        Ok(())
     }
 }
 
@@ -85,15 +425,13 @@ impl AsClassManager for SqliteClassManager {
     {
         Box::pin(async move {
             for target in &target_v {
-                sqlx::query(&format!(
-                    "DELETE FROM class_t WHERE class=? AND source=? AND target=?"
-                ))
-                .bind(class)
-                .bind(source)
-                .bind(target)
-                .execute(&self.pool)
-                .await
-                .change_context(moon_class::err::Error::RuntimeError)?;
+                self.execute(
+                    sqlx::query("DELETE FROM class_t WHERE class=? AND source=? AND target=?")
+                        .bind(class)
+                        .bind(source)
+                        .bind(target),
+                )
+                .await?;
             }
 
             Ok(())
@@ -112,16 +450,16 @@ impl AsClassManager for SqliteClassManager {
         'a2: 'f,
     {
         Box::pin(async move {
+            // Duplicates of set classes hit a unique index, other failures are errors.
+            let sql = if self.is_set(class).await? {
+                "INSERT OR IGNORE INTO class_t(class, source, target) VALUES (?, ?, ?)"
+            } else {
+                "INSERT INTO class_t(class, source, target) VALUES (?, ?, ?)"
+            };
+
             for target in &target_v {
-                sqlx::query(&format!(
-                    "INSERT INTO class_t(class, source, target) VALUES (?, ?, ?)"
-                ))
-                .bind(class)
-                .bind(source)
-                .bind(target)
-                .execute(&self.pool)
-                .await
-                .change_context(moon_class::err::Error::RuntimeError)?;
+                self.execute(sqlx::query(sql).bind(class).bind(source).bind(target))
+                    .await?;
             }
 
             Ok(())
@@ -139,56 +477,477 @@ impl AsClassManager for SqliteClassManager {
         'a2: 'f,
     {
         Box::pin(async move {
-            match class {
-                "#source" => {
-                    let data = json::parse(source).unwrap();
+            let mut arr = vec![];
 
-                    self.get_source(
-                        data["$target"][0].as_str().unwrap(),
-                        data["$class"][0].as_str().unwrap(),
+            let rs = self
+                .fetch_all(
+                    sqlx::query(
+                        "SELECT target FROM class_t WHERE class=? AND source =? ORDER BY id",
                     )
-                    .await
+                    .bind(class)
+                    .bind(source),
+                )
+                .await?;
+
+            for row in rs {
+                arr.push(row.get(0));
+            }
+
+            Ok(arr)
+        })
+    }
+
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let rs = self
+                .fetch_all(
+                    sqlx::query(
+                        "SELECT source FROM class_t WHERE target=? AND class=? ORDER BY id",
+                    )
+                    .bind(target)
+                    .bind(class),
+                )
+                .await?;
+
+            let mut arr = vec![];
+
+            for row in rs {
+                arr.push(row.get(0));
+            }
+
+            Ok(arr)
+        })
+    }
+
+    /// Runs in a transaction, so that other connections can not write in between.
+    fn compare_and_set<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        expected_v: Vec<String>,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<bool>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            self.begin().await?;
+
+            let rs: err::Result<bool> = async {
+                let current_v = self.get(class, source).await?;
+
+                if current_v != expected_v {
+                    return Ok(false);
                 }
-                _ => {
-                    let mut arr = vec![];
 
-                    let rs = sqlx::query(&format!(
-                        "SELECT target FROM class_t WHERE class=? AND source =? ORDER BY id"
-                    ))
-                    .bind(class)
-                    .bind(source)
-                    .fetch_all(&self.pool)
-                    .await
-                    .change_context(moon_class::err::Error::RuntimeError)?;
+                self.remove(class, source, current_v).await?;
+                self.append(class, source, target_v).await?;
+
+                Ok(true)
+            }
+            .await;
 
-                    for row in rs {
-                        arr.push(row.get(0));
+            match rs {
+                Ok(is_set) => {
+                    self.commit().await?;
+
+                    Ok(is_set)
+                }
+                Err(e) => {
+                    if let Err(rollback_e) = self.rollback().await {
+                        log::error!("compare_and_set: rollback failed: {rollback_e:?}");
                     }
 
-                    Ok(arr)
+                    Err(e)
                 }
             }
         })
     }
+
+    fn insert<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let is_set = self.is_set(class).await?;
+
+            self.edit_tail(class, source, |mut list| {
+                // Inserting what a set has would move it instead of being ignored.
+                let target_v = target_v
+                    .into_iter()
+                    .filter(|target| !is_set || !list.contains(target))
+                    .collect();
+
+                util::insert_at(&mut list, index, target_v)?;
+
+                Ok((index, list.split_off(index)))
+            })
+            .await
+        })
+    }
+
+    fn remove_at<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let mut target = String::new();
+
+            self.edit_tail(class, source, |mut list| {
+                target = util::remove_at(&mut list, index)?;
+
+                Ok((index, list.split_off(index)))
+            })
+            .await?;
+
+            Ok(target)
+        })
+    }
+
+    fn move_to<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        from: usize,
+        to: usize,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            self.edit_tail(class, source, |mut list| {
+                util::move_to(&mut list, from, to)?;
+
+                let start = from.min(to);
+
+                Ok((start, list.split_off(start)))
+            })
+            .await
+        })
+    }
+
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            self.begin().await?;
+
+            match self.delete_object(source, is_cascade).await {
+                Ok(deleted_v) => {
+                    self.commit().await?;
+
+                    Ok(deleted_v)
+                }
+                Err(e) => {
+                    if let Err(rollback_e) = self.rollback().await {
+                        log::error!("delete: rollback failed: {rollback_e:?}");
+                    }
+
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Breadth first one distance at a time, the neighbours of a whole frontier in one query.
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let (from, to) = if is_reverse {
+                ("target", "source")
+            } else {
+                ("source", "target")
+            };
+            let mut bfs = util::Bfs::new(start);
+
+            while bfs.node().is_some() {
+                let frontier = bfs.frontier();
+                let mut next_mp: HashMap<String, Vec<String>> = HashMap::new();
+
+                // Under the limit of bound parameters of older SQLite versions.
+                for chunk in frontier.chunks(900) {
+                    let sql = format!(
+                        "SELECT {from}, {to} FROM class_t WHERE class=? AND {from} IN ({}) ORDER BY id",
+                        vec!["?"; chunk.len()].join(", ")
+                    );
+                    let mut query = sqlx::query(&sql).bind(class);
+
+                    for node in chunk {
+                        query = query.bind(node);
+                    }
+
+                    for row in self.fetch_all(query).await? {
+                        next_mp.entry(row.get(0)).or_default().push(row.get(1));
+                    }
+                }
+
+                for node in &frontier {
+                    bfs.expand(next_mp.remove(node).unwrap_or_default());
+                }
+            }
+
+            Ok(bfs.into_step_v())
+        })
+    }
+
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let mut condition_v = vec![];
+
+            for (column, value) in [
+                ("class", &pattern.class),
+                ("source", &pattern.source),
+                ("target", &pattern.target),
+            ] {
+                if value.is_some() {
+                    condition_v.push(format!("{column}=?"));
+                }
+            }
+
+            let sql = if condition_v.is_empty() {
+                "SELECT class, source, target FROM class_t ORDER BY id".to_string()
+            } else {
+                format!(
+                    "SELECT class, source, target FROM class_t WHERE {} ORDER BY id",
+                    condition_v.join(" AND ")
+                )
+            };
+
+            let mut query = sqlx::query(&sql);
+
+            for value in [&pattern.class, &pattern.source, &pattern.target]
+                .into_iter()
+                .flatten()
+            {
+                query = query.bind(value);
+            }
+
+            Ok(self
+                .fetch_all(query)
+                .await?
+                .iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect())
+        })
+    }
+
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            self.execute(
+                sqlx::query("INSERT OR IGNORE INTO object_t(source) VALUES (?)").bind(source),
+            )
+            .await
+        })
+    }
+
+    fn object_v<'a, 'f>(
+        &'a self,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { Ok(self.object_set().await?.into_iter().collect()) })
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(self.mark_from(root_v))
+    }
+
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.begin().await?;
+
+            match self.sweep_unmarked(live_v).await {
+                Ok(garbage_v) => {
+                    self.commit().await?;
+
+                    Ok(garbage_v)
+                }
+                Err(e) => {
+                    if let Err(rollback_e) = self.rollback().await {
+                        log::error!("sweep: rollback failed: {rollback_e:?}");
+                    }
+
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// The outermost transaction is `BEGIN IMMEDIATE`, taking the write lock at once.
+    ///
+    /// Nested transactions are savepoints.
+    fn begin<'a, 'f>(
+        &'a mut self,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let mut tx = self.tx.lock().await;
+
+            match tx.as_mut() {
+                Some((conn, depth)) => {
+                    let sql = format!("SAVEPOINT sp_{depth}");
+
+                    sqlx::query(&sql)
+                        .execute(&mut **conn)
+                        .await
+                        .change_context(moon_class::err::Error::RuntimeError)?;
+
+                    *depth += 1;
+                }
+                None => {
+                    let mut conn = self
+                        .pool
+                        .acquire()
+                        .await
+                        .change_context(moon_class::err::Error::RuntimeError)?;
+
+                    sqlx::query("BEGIN IMMEDIATE")
+                        .execute(&mut *conn)
+                        .await
+                        .change_context(moon_class::err::Error::RuntimeError)?;
+
+                    *tx = Some((conn, 1));
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn commit<'a, 'f>(
+        &'a mut self,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { self.end(true).await })
+    }
+
+    fn rollback<'a, 'f>(
+        &'a mut self,
+    ) -> Pin<Box<dyn moon_class::def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { self.end(false).await })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use moon_class::{executor::ClassExecutor, ClassManager};
+    use moon_class::{
+        def::{AsClassManager, Pattern},
+        executor::ClassExecutor,
+        ClassManager,
+    };
+    use sqlx::{sqlite::SqlitePoolOptions, Row};
 
-    #[test]
-    fn test_add() {
+    use crate::SqliteClassManager;
+
+    /// Runs `fu` to completion on a multi-threaded runtime, with logs enabled.
+    fn block_on<F: std::future::Future>(fu: F) -> F::Output {
         let _ =
             env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
                 .is_test(true)
                 .try_init();
 
-        let rt = tokio::runtime::Builder::new_multi_thread()
+        tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
+            .unwrap()
+            .block_on(fu)
+    }
+
+    /// An initialized manager over a memory database.
+    async fn memory_cm() -> SqliteClassManager {
+        // One connection, or every connection would open its own memory database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
             .unwrap();
 
-        rt.block_on(async {
+        let cm = SqliteClassManager::new(pool);
+
+        cm.init().await;
+
+        cm
+    }
+
+    #[test]
+    fn test_add() {
+        block_on(async {
             log::debug!("start");
 
             let mut cm = ClassManager::new();
@@ -206,4 +965,298 @@ mod tests {
             assert_eq!(rs[0], "2");
         })
     }
+
+    #[test]
+    fn test_transaction() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_atomic(true)
+                .execute_script("home = view(main); \"boom\" = #throw();")
+                .await;
+
+            assert!(rs.is_err());
+            assert!(cm.get("view", "main").await.unwrap().is_empty());
+
+            cm.begin().await.unwrap();
+            cm.append("log", "main", vec!["1".to_string()])
+                .await
+                .unwrap();
+            cm.begin().await.unwrap();
+            cm.append("log", "main", vec!["2".to_string()])
+                .await
+                .unwrap();
+            cm.rollback().await.unwrap();
+            cm.commit().await.unwrap();
+
+            assert_eq!(cm.get("log", "main").await.unwrap(), ["1"]);
+
+            assert!(cm
+                .compare_and_set("log", "main", vec!["1".to_string()], vec!["2".to_string()])
+                .await
+                .unwrap());
+            assert!(!cm
+                .compare_and_set("log", "main", vec!["1".to_string()], vec!["3".to_string()])
+                .await
+                .unwrap());
+            assert_eq!(cm.get("log", "main").await.unwrap(), ["2"]);
+
+            cm.insert("log", "main", 0, vec!["0".to_string(), "1".to_string()])
+                .await
+                .unwrap();
+            cm.move_to("log", "main", 2, 0).await.unwrap();
+
+            assert_eq!(cm.remove_at("log", "main", 1).await.unwrap(), "0");
+            assert_eq!(cm.get("log", "main").await.unwrap(), ["2", "1"]);
+        })
+    }
+
+    #[test]
+    fn test_set() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+            cm.mark_set("tag").await.unwrap();
+
+            ClassExecutor::new(&mut cm)
+                .execute_script("[a, b, a] = tag(x); [b, c] = tag(x); [a, a] = note(x);")
+                .await
+                .unwrap();
+
+            cm.insert("tag", "x", 0, vec!["c".to_string(), "d".to_string()])
+                .await
+                .unwrap();
+
+            assert_eq!(cm.get("tag", "x").await.unwrap(), ["d", "a", "b", "c"]);
+            assert_eq!(cm.get("note", "x").await.unwrap(), ["a", "a"]);
+
+            // Quotes in the class stay in the name and the condition of its index.
+            let class = "it's \"odd\"'); DROP TABLE class_t; --";
+
+            cm.mark_set(class).await.unwrap();
+            cm.append(class, "x", vec!["a".to_string(), "a".to_string()])
+                .await
+                .unwrap();
+
+            assert!(cm.is_set(class).await.unwrap());
+            assert!(!cm.is_set("note").await.unwrap());
+            assert_eq!(cm.get(class, "x").await.unwrap(), ["a"]);
+            assert!(cm.mark_set("a\0b").await.is_err());
+
+            cm.begin().await.unwrap();
+            cm.mark_set("draft").await.unwrap();
+
+            assert!(cm.is_set("draft").await.unwrap());
+
+            cm.rollback().await.unwrap();
+
+            assert!(!cm.is_set("draft").await.unwrap());
+
+            assert!(cm.mark_all_sets().await.is_err());
+        })
+    }
+
+    #[test]
+    fn test_delete() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+
+            ClassExecutor::new(&mut cm)
+                .execute_script(
+                    r#"
+{name: a, item: {name: x}} = child(root);
+{name: b} = child(root);
+#index({$source: child(root), $index: 0}) = #delete(cascade);
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            let child_v = cm.get("child", "root").await.unwrap();
+
+            assert_eq!(child_v.len(), 1);
+            assert!(cm.get_source("x", "name").await.unwrap().is_empty());
+            assert!(cm.dangling_v().await.unwrap().is_empty());
+
+            cm.remove("name", &child_v[0], vec!["b".to_string()])
+                .await
+                .unwrap();
+
+            assert_eq!(
+                cm.dangling_v().await.unwrap(),
+                [("child".to_string(), "root".to_string(), child_v[0].clone())]
+            );
+        })
+    }
+
+    #[test]
+    fn test_gc() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+
+            let rs = ClassExecutor::new(&mut cm)
+                .execute_script(
+                    r#"
+{name: a, item: {name: x}} = child(root);
+{name: b, item: {name: y}} = $tmp();
+{name: c} = $result();
+alice := name(123e4567-e89b-12d3-a456-426614174000);
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            let garbage_v = cm.collect_garbage(rs.clone()).await.unwrap();
+
+            assert_eq!(garbage_v.len(), 2);
+            assert_eq!(
+                cm.get("name", "123e4567-e89b-12d3-a456-426614174000")
+                    .await
+                    .unwrap(),
+                ["alice"]
+            );
+            assert_eq!(cm.get_source("x", "name").await.unwrap().len(), 1);
+            assert_eq!(cm.get_source("c", "name").await.unwrap(), rs);
+            assert!(cm.get_source("y", "name").await.unwrap().is_empty());
+        })
+    }
+
+    #[test]
+    fn test_query() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+
+            ClassExecutor::new(&mut cm)
+                .execute_script("home = view(main); dark = theme(main); main = layout(app);")
+                .await
+                .unwrap();
+
+            let edge = |class: &str, source: &str, target: &str| {
+                (class.to_string(), source.to_string(), target.to_string())
+            };
+
+            assert_eq!(
+                cm.query(Pattern {
+                    source: Some("main".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap(),
+                [edge("view", "main", "home"), edge("theme", "main", "dark")]
+            );
+            assert_eq!(
+                cm.query(Pattern {
+                    target: Some("main".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap(),
+                [edge("layout", "app", "main")]
+            );
+            assert_eq!(cm.query(Pattern::default()).await.unwrap().len(), 3);
+
+            // Queries by source, dangling references included, search instead of scanning.
+            for sql in [
+                "SELECT class, source, target FROM class_t WHERE source = 'main' ORDER BY id",
+                "SELECT 1 FROM class_t a WHERE NOT EXISTS (SELECT 1 FROM class_t b WHERE b.source = a.target)",
+            ] {
+                let detail_v = cm
+                    .fetch_all(sqlx::query(&format!("EXPLAIN QUERY PLAN {sql}")))
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|row| row.get::<String, _>(3))
+                    .collect::<Vec<String>>();
+
+                assert!(
+                    detail_v.iter().any(|detail| detail.contains("class_t_source_class")),
+                    "{sql}: {detail_v:?}"
+                );
+            }
+        })
+    }
+
+    #[test]
+    fn test_graph() {
+        block_on(async {
+            let mut cm = memory_cm().await;
+
+            ClassExecutor::new(&mut cm)
+                .execute_script("[b, c] = child(a); d = child(b); e = child(c); a = child(d);")
+                .await
+                .unwrap();
+
+            assert_eq!(
+                cm.reachable("child", "a").await.unwrap(),
+                ["b", "c", "d", "e", "a"]
+            );
+            assert_eq!(
+                cm.ancestors("child", "e").await.unwrap(),
+                ["c", "a", "d", "b"]
+            );
+            assert_eq!(
+                cm.path("child", "b", "e").await.unwrap(),
+                ["b", "d", "a", "c", "e"]
+            );
+            assert!(cm.path("child", "e", "a").await.unwrap().is_empty());
+            assert_eq!(cm.depth("child", "b").await.unwrap(), 3);
+
+            // Steps, parents and distances as the native breadth-first search finds them.
+            let mut native = ClassManager::new();
+
+            ClassExecutor::new(&mut native)
+                .execute_script("[b, c] = child(a); d = child(b); e = child(c); a = child(d);")
+                .await
+                .unwrap();
+
+            for (start, is_reverse) in [("a", false), ("e", true)] {
+                assert_eq!(
+                    cm.traverse("child", start, is_reverse).await.unwrap(),
+                    native.traverse("child", start, is_reverse).await.unwrap()
+                );
+            }
+
+            // Edges appended out of the order the queue reaches their sources.
+            let script = "[b, c] = next(a); e = next(c); d = next(b);";
+
+            ClassExecutor::new(&mut cm)
+                .execute_script(script)
+                .await
+                .unwrap();
+            ClassExecutor::new(&mut native)
+                .execute_script(script)
+                .await
+                .unwrap();
+
+            assert_eq!(
+                cm.reachable("next", "a").await.unwrap(),
+                ["b", "c", "d", "e"]
+            );
+
+            // A complete graph, every node is reached once.
+            for source in 0..20 {
+                let target_v = (0..20)
+                    .filter(|target| *target != source)
+                    .map(|target| target.to_string())
+                    .collect::<Vec<String>>();
+
+                cm.append("link", &source.to_string(), target_v.clone())
+                    .await
+                    .unwrap();
+                native
+                    .append("link", &source.to_string(), target_v)
+                    .await
+                    .unwrap();
+            }
+
+            for (class, start) in [("next", "a"), ("link", "0")] {
+                assert_eq!(
+                    cm.traverse(class, start, false).await.unwrap(),
+                    native.traverse(class, start, false).await.unwrap()
+                );
+            }
+
+            assert_eq!(cm.reachable("link", "0").await.unwrap().len(), 20);
+        })
+    }
 }
@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Item {
     pub class: String,
     pub source: String,
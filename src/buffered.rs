@@ -0,0 +1,341 @@
+use std::pin::Pin;
+
+use error_stack::ResultExt;
+
+use crate::{
+    def::{self, AsClassManager, Edge, Fu, Pattern, Step},
+    err,
+};
+
+enum Op {
+    Append,
+    Remove,
+}
+
+/// Gives transactions to a manager without them by holding writes in memory until commit.
+///
+/// Outside of a transaction writes go straight to the inner manager. Inside, objects are
+/// deleted and collected through the buffered [`AsClassManager::query`].
+pub struct Buffered<CM> {
+    inner: CM,
+    write_v: Vec<(Op, String, String, Vec<String>)>,
+    savepoint_v: Vec<usize>,
+}
+
+impl<CM> Buffered<CM> {
+    pub fn new(inner: CM) -> Self {
+        Self {
+            inner,
+            write_v: vec![],
+            savepoint_v: vec![],
+        }
+    }
+
+    pub fn inner(&self) -> &CM {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> CM {
+        self.inner
+    }
+}
+
+impl<CM: AsClassManager> AsClassManager for Buffered<CM> {
+    fn get<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        source: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let mut rs = self.inner.get(class, source).await?;
+
+            // Pending writes are visible inside the transaction.
+            for (op, w_class, w_source, target_v) in &self.write_v {
+                if w_class != class || w_source != source {
+                    continue;
+                }
+
+                match op {
+                    Op::Append => rs.extend(target_v.iter().cloned()),
+                    Op::Remove => rs.retain(|target| !target_v.contains(target)),
+                }
+            }
+
+            Ok(rs)
+        })
+    }
+
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let mut rs = self.inner.get_source(target, class).await?;
+
+            for (op, w_class, w_source, target_v) in &self.write_v {
+                if w_class != class || !target_v.iter().any(|t| t == target) {
+                    continue;
+                }
+
+                match op {
+                    Op::Append => rs.push(w_source.clone()),
+                    Op::Remove => rs.retain(|source| source != w_source),
+                }
+            }
+
+            Ok(rs)
+        })
+    }
+
+    /// Walks the pending writes too, the inner traversal once there are none.
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        if self.write_v.is_empty() {
+            self.inner.traverse(class, start, is_reverse)
+        } else {
+            def::traverse_by_get(self, class, start, is_reverse)
+        }
+    }
+
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let mut rs = self.inner.query(pattern.clone()).await?;
+
+            for (op, class, source, target_v) in &self.write_v {
+                for target in target_v {
+                    if !pattern.matches(class, source, target) {
+                        continue;
+                    }
+
+                    match op {
+                        Op::Append => rs.push((class.clone(), source.clone(), target.clone())),
+                        Op::Remove => rs.retain(|(c, s, t)| (c, s, t) != (class, source, target)),
+                    }
+                }
+            }
+
+            Ok(rs)
+        })
+    }
+
+    fn remove<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if self.savepoint_v.is_empty() {
+                return self.inner.remove(class, source, target_v).await;
+            }
+
+            self.write_v
+                .push((Op::Remove, class.to_string(), source.to_string(), target_v));
+
+            Ok(())
+        })
+    }
+
+    fn append<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if self.savepoint_v.is_empty() {
+                return self.inner.append(class, source, target_v).await;
+            }
+
+            self.write_v
+                .push((Op::Append, class.to_string(), source.to_string(), target_v));
+
+            Ok(())
+        })
+    }
+
+    fn compare_and_set<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        expected_v: Vec<String>,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<bool>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if self.savepoint_v.is_empty() {
+                return self
+                    .inner
+                    .compare_and_set(class, source, expected_v, target_v)
+                    .await;
+            }
+
+            let current_v = self.get(class, source).await?;
+
+            if current_v != expected_v {
+                return Ok(false);
+            }
+
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, target_v).await?;
+
+            Ok(true)
+        })
+    }
+
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        if self.savepoint_v.is_empty() {
+            self.inner.delete(source, is_cascade)
+        } else {
+            def::delete_by_query(self, source, is_cascade)
+        }
+    }
+
+    /// Declared at once: rolled back, the object has no fields and the next sweep forgets it.
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        self.inner.declare_object(source)
+    }
+
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.object_v()
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        if self.write_v.is_empty() {
+            self.inner.mark(root_v)
+        } else {
+            def::mark_by_query(self, root_v)
+        }
+    }
+
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        if self.savepoint_v.is_empty() {
+            self.inner.sweep(live_v)
+        } else {
+            def::sweep_by_query(self, live_v)
+        }
+    }
+
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.savepoint_v.push(self.write_v.len());
+
+            Ok(())
+        })
+    }
+
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.savepoint_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("commit: no transaction")?;
+
+            if !self.savepoint_v.is_empty() {
+                return Ok(());
+            }
+
+            for (op, class, source, target_v) in std::mem::take(&mut self.write_v) {
+                match op {
+                    Op::Append => self.inner.append(&class, &source, target_v).await?,
+                    Op::Remove => self.inner.remove(&class, &source, target_v).await?,
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let savepoint = self
+                .savepoint_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("rollback: no transaction")?;
+
+            self.write_v.truncate(savepoint);
+
+            Ok(())
+        })
+    }
+}
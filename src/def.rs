@@ -1,7 +1,12 @@
-use std::{future::Future, pin::Pin};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+};
 
-use crate::err;
+use error_stack::ResultExt;
 
+use crate::{err, util};
 
 #[cfg(any(target_family = "wasm", feature = "no_send"))]
 pub trait AsSendSyncOption {}
@@ -31,6 +36,35 @@ pub trait Fu: Future + AsSendOption {}
 
 impl<T: Future + AsSendOption> Fu for T {}
 
+/// `(class, source, target)`.
+pub type Edge = (String, String, String);
+
+/// A node reached by [`AsClassManager::traverse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub node: String,
+    /// The node it was reached from.
+    pub parent: String,
+    /// Edges from the start.
+    pub distance: usize,
+}
+
+/// A `(class, source, target)` pattern, `None` matches anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pattern {
+    pub class: Option<String>,
+    pub source: Option<String>,
+    pub target: Option<String>,
+}
+
+impl Pattern {
+    pub fn matches(&self, class: &str, source: &str, target: &str) -> bool {
+        self.class.as_ref().is_none_or(|c| c == class)
+            && self.source.as_ref().is_none_or(|s| s == source)
+            && self.target.as_ref().is_none_or(|t| t == target)
+    }
+}
+
 pub trait AsClassManager: AsSendSyncOption {
     fn get<'a, 'a1, 'a2, 'f>(
         &'a self,
@@ -63,6 +97,595 @@ pub trait AsClassManager: AsSendSyncOption {
         'a: 'f,
         'a1: 'f,
         'a2: 'f;
+
+    /// Sources of `class` having `target`, the reverse of [`AsClassManager::get`].
+    ///
+    /// Required: no other method finds the sources of a class.
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f;
+
+    /// Edges `(class, source, target)` matching `pattern`, in the order they were appended.
+    ///
+    /// Managers without indexes for the other patterns only answer a bound class with a
+    /// bound source or target.
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            match (&pattern.class, &pattern.source, &pattern.target) {
+                (Some(class), Some(source), _) => Ok(self
+                    .get(class, source)
+                    .await?
+                    .into_iter()
+                    .filter(|target| pattern.matches(class, source, target))
+                    .map(|target| (class.clone(), source.clone(), target))
+                    .collect()),
+                (Some(class), None, Some(target)) => Ok(self
+                    .get_source(target, class)
+                    .await?
+                    .into_iter()
+                    .map(|source| (class.clone(), source, target.clone()))
+                    .collect()),
+                _ => Err(err::Error::Unsupported)
+                    .attach_printable_lazy(|| format!("query: {pattern:?} is not supported")),
+            }
+        })
+    }
+
+    /// Nodes reached from `start` breadth first along `class`, against it if `is_reverse`.
+    ///
+    /// Every node is reached once, `start` only if it is on a cycle.
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        traverse_by_get(self, class, start, is_reverse)
+    }
+
+    /// Nodes reachable from `source` along `class`.
+    fn reachable<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        source: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let step_v = self.traverse(class, source, false).await?;
+
+            Ok(step_v.into_iter().map(|step| step.node).collect())
+        })
+    }
+
+    /// Nodes reaching `target` along `class`, the closest first.
+    fn ancestors<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        target: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let step_v = self.traverse(class, target, true).await?;
+
+            Ok(step_v.into_iter().map(|step| step.node).collect())
+        })
+    }
+
+    /// A shortest path from `from` to `to` along `class`, both included, empty if there is none.
+    fn path<'a, 'a1, 'a2, 'a3, 'f>(
+        &'a self,
+        class: &'a1 str,
+        from: &'a2 str,
+        to: &'a3 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+        'a3: 'f,
+    {
+        Box::pin(async move {
+            if from == to {
+                return Ok(vec![from.to_string()]);
+            }
+
+            let step_v = self.traverse(class, from, false).await?;
+
+            Ok(util::path_of(&step_v, from, to))
+        })
+    }
+
+    /// Edges from `target` up to its farthest ancestor along `class`, `0` for a root.
+    fn depth<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        target: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<usize>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let step_v = self.traverse(class, target, true).await?;
+
+            Ok(step_v.last().map_or(0, |step| step.distance))
+        })
+    }
+
+    /// Replaces the targets of `class(source)` with `target_v` if they are `expected_v`.
+    ///
+    /// Returns `false` and changes nothing otherwise, set-if-empty expects nothing.
+    fn compare_and_set<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        expected_v: Vec<String>,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<bool>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let current_v = self.get(class, source).await?;
+
+            if current_v != expected_v {
+                return Ok(false);
+            }
+
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, target_v).await?;
+
+            Ok(true)
+        })
+    }
+
+    /// Inserts `target_v` into the targets of `class(source)` before `index`.
+    ///
+    /// `index` may be the number of targets to append, `0` prepends.
+    fn insert<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let current_v = self.get(class, source).await?;
+            let mut list = current_v.clone();
+
+            util::insert_at(&mut list, index, target_v)?;
+
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, list).await
+        })
+    }
+
+    /// Removes the target of `class(source)` at `index`, leaving its duplicates.
+    fn remove_at<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let current_v = self.get(class, source).await?;
+            let mut list = current_v.clone();
+
+            let target = util::remove_at(&mut list, index)?;
+
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, list).await?;
+
+            Ok(target)
+        })
+    }
+
+    /// Moves the target of `class(source)` at `from` so that it ends up at `to`.
+    fn move_to<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        from: usize,
+        to: usize,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let current_v = self.get(class, source).await?;
+            let mut list = current_v.clone();
+
+            util::move_to(&mut list, from, to)?;
+
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, list).await
+        })
+    }
+
+    /// Deletes the object `source`: its fields and the references to it.
+    ///
+    /// With `is_cascade`, the objects only it references are deleted too. Returns the deleted objects.
+    ///
+    /// The default needs [`AsClassManager::query`] to answer a bound source or target alone.
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        delete_by_query(self, source, is_cascade)
+    }
+
+    /// Declares `source` an object, whose id an executor minted.
+    ///
+    /// Only declared objects are collected, a named source is kept even if it looks like an
+    /// id. The default declares nothing, so nothing is collected.
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        _source: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Sources declared objects by [`AsClassManager::declare_object`].
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async { Ok(vec![]) })
+    }
+
+    /// Ids reachable from `root_v` and from every named source, one not declared an object.
+    ///
+    /// The default needs [`AsClassManager::query`] to answer an unbound pattern.
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        mark_by_query(self, root_v)
+    }
+
+    /// Deletes the fields of the declared objects not in `live_v`, returns them.
+    ///
+    /// Managers declaring objects forget the ones not in `live_v`. The default needs
+    /// [`AsClassManager::query`] to answer an unbound pattern.
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        sweep_by_query(self, live_v)
+    }
+
+    /// Deletes the objects unreachable from `root_v` and the named sources.
+    fn collect_garbage<'a, 'f>(
+        &'a mut self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let live_v = self.mark(root_v).await?;
+
+            self.sweep(live_v).await
+        })
+    }
+
+    /// Starts a transaction, nested in the current one if any.
+    ///
+    /// Executors keep a [`crate::executor::journal::Journal`] for managers without
+    /// transactions, other callers can wrap them in [`crate::buffered::Buffered`].
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            Err(err::Error::Unsupported).attach_printable("begin: transactions are not supported")
+        })
+    }
+
+    /// Keeps the writes of the innermost transaction.
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            Err(err::Error::Unsupported).attach_printable("commit: transactions are not supported")
+        })
+    }
+
+    /// Discards the writes of the innermost transaction.
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            Err(err::Error::Unsupported)
+                .attach_printable("rollback: transactions are not supported")
+        })
+    }
+}
+
+/// [`AsClassManager::traverse`] through [`AsClassManager::get`] and
+/// [`AsClassManager::get_source`], for managers without a traversal of their own.
+pub fn traverse_by_get<'a, 'a1, 'a2, 'f, CM>(
+    cm: &'a CM,
+    class: &'a1 str,
+    start: &'a2 str,
+    is_reverse: bool,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    CM: AsClassManager + ?Sized,
+{
+    Box::pin(async move {
+        let mut bfs = util::Bfs::new(start);
+
+        while let Some(node) = bfs.node() {
+            let next_v = if is_reverse {
+                cm.get_source(node, class).await?
+            } else {
+                cm.get(class, node).await?
+            };
+
+            bfs.expand(next_v);
+        }
+
+        Ok(bfs.into_step_v())
+    })
+}
+
+/// Edges matching `pattern` but not in `removed_set`.
+async fn query_except<CM: AsClassManager + ?Sized>(
+    cm: &CM,
+    pattern: Pattern,
+    removed_set: &HashSet<Edge>,
+) -> err::Result<Vec<Edge>> {
+    let mut edge_v = cm.query(pattern).await?;
+
+    edge_v.retain(|edge| !removed_set.contains(edge));
+
+    Ok(edge_v)
+}
+
+/// [`AsClassManager::delete`] through [`AsClassManager::query`] and [`AsClassManager::remove`].
+///
+/// Removed edges are tracked, managers whose reads miss their own writes cascade too.
+pub fn delete_by_query<'a, 'a1, 'f, CM>(
+    cm: &'a mut CM,
+    source: &'a1 str,
+    is_cascade: bool,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    CM: AsClassManager + ?Sized,
+{
+    Box::pin(async move {
+        let mut deleted_v: Vec<String> = vec![];
+        let mut removed_set: HashSet<Edge> = HashSet::new();
+        let mut pending_v = vec![source.to_string()];
+
+        while let Some(source) = pending_v.pop() {
+            if deleted_v.contains(&source) {
+                continue;
+            }
+
+            let mut edge_v = query_except(
+                cm,
+                Pattern {
+                    source: Some(source.clone()),
+                    ..Default::default()
+                },
+                &removed_set,
+            )
+            .await?;
+
+            edge_v.extend(
+                query_except(
+                    cm,
+                    Pattern {
+                        target: Some(source.clone()),
+                        ..Default::default()
+                    },
+                    &removed_set,
+                )
+                .await?,
+            );
+
+            let mut child_v = vec![];
+
+            for edge in edge_v {
+                if !removed_set.insert(edge.clone()) {
+                    continue;
+                }
+
+                let (class, edge_source, target) = edge;
+
+                cm.remove(&class, &edge_source, vec![target.clone()])
+                    .await?;
+
+                if edge_source == source {
+                    child_v.push(target);
+                }
+            }
+
+            if is_cascade {
+                // Owned exclusively: an object no one references any more.
+                for child in child_v {
+                    let field_v = query_except(
+                        cm,
+                        Pattern {
+                            source: Some(child.clone()),
+                            ..Default::default()
+                        },
+                        &removed_set,
+                    )
+                    .await?;
+                    let ref_v = query_except(
+                        cm,
+                        Pattern {
+                            target: Some(child.clone()),
+                            ..Default::default()
+                        },
+                        &removed_set,
+                    )
+                    .await?;
+
+                    if !field_v.is_empty() && ref_v.is_empty() {
+                        pending_v.push(child);
+                    }
+                }
+            }
+
+            deleted_v.push(source);
+        }
+
+        Ok(deleted_v)
+    })
+}
+
+/// [`AsClassManager::mark`] through [`AsClassManager::query`].
+pub fn mark_by_query<'a, 'f, CM>(
+    cm: &'a CM,
+    root_v: Vec<String>,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+where
+    'a: 'f,
+    CM: AsClassManager + ?Sized,
+{
+    Box::pin(async move {
+        let edge_v = cm.query(Pattern::default()).await?;
+        let object_set = cm
+            .object_v()
+            .await?
+            .into_iter()
+            .collect::<HashSet<String>>();
+        let mut field_mp: HashMap<&str, Vec<&str>> = HashMap::new();
+
+        for (_, source, target) in &edge_v {
+            field_mp.entry(source).or_default().push(target);
+        }
+
+        let mut live_set = HashSet::new();
+        let mut pending_v = root_v;
+
+        pending_v.extend(
+            field_mp
+                .keys()
+                .filter(|source| !object_set.contains(**source))
+                .map(|source| source.to_string()),
+        );
+
+        while let Some(source) = pending_v.pop() {
+            if live_set.contains(&source) {
+                continue;
+            }
+
+            if let Some(target_v) = field_mp.get(source.as_str()) {
+                pending_v.extend(target_v.iter().map(|target| target.to_string()));
+            }
+
+            live_set.insert(source);
+        }
+
+        Ok(live_set.into_iter().collect())
+    })
+}
+
+/// [`AsClassManager::sweep`] through [`AsClassManager::query`] and [`AsClassManager::remove`].
+pub fn sweep_by_query<'a, 'f, CM>(
+    cm: &'a mut CM,
+    live_v: Vec<String>,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+where
+    'a: 'f,
+    CM: AsClassManager + ?Sized,
+{
+    Box::pin(async move {
+        let live_set = live_v.into_iter().collect::<HashSet<String>>();
+        let object_set = cm
+            .object_v()
+            .await?
+            .into_iter()
+            .collect::<HashSet<String>>();
+        let mut list_mp: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+
+        for (class, source, target) in cm.query(Pattern::default()).await? {
+            if object_set.contains(&source) && !live_set.contains(&source) {
+                list_mp.entry((class, source)).or_default().push(target);
+            }
+        }
+
+        let mut garbage_v = vec![];
+
+        for ((class, source), target_v) in list_mp {
+            cm.remove(&class, &source, target_v).await?;
+
+            if !garbage_v.contains(&source) {
+                garbage_v.push(source);
+            }
+        }
+
+        garbage_v.sort();
+
+        Ok(garbage_v)
+    })
 }
 
 pub trait AsSetable {
@@ -1,6 +1,6 @@
 use std::fmt::Display;
 
-use error_stack::Context;
+use error_stack::{Context, Report};
 
 #[derive(Debug)]
 pub enum Error {
@@ -13,6 +13,32 @@ pub enum Error {
     SyntaxError,
     /// RuntimeError
     RuntimeError,
+    /// Some execution budget was exhausted.
+    LimitExceeded,
+    /// Execution ran out of time.
+    Timeout,
+    /// Execution was cancelled by the host.
+    Cancelled,
+    /// The manager does not support the operation.
+    Unsupported,
+    /// A compare-and-set found other targets than expected.
+    Conflict,
+    /// A target does not have the value type declared for its class.
+    TypeMismatch,
+    /// A class declared to hold one target would hold more.
+    CardinalityViolated,
+    /// A required class would be left without targets.
+    MissingRequired,
+}
+
+impl Error {
+    /// Name of the error as seen by scripts, `#throw(kind)` raises `Other(kind)`.
+    pub fn kind(&self) -> String {
+        match self {
+            Error::Other(kind) => kind.clone(),
+            _ => format!("{self:?}"),
+        }
+    }
 }
 
 impl Display for Error {
@@ -24,3 +50,20 @@ impl Display for Error {
 impl Context for Error {}
 
 pub type Result<T> = error_stack::Result<T, Error>;
+
+/// Printable attachments of a report, innermost first.
+pub fn message(report: &Report<Error>) -> String {
+    let mut line_v = report
+        .frames()
+        .filter_map(|frame| {
+            frame
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| frame.downcast_ref::<&str>().map(|s| s.to_string()))
+        })
+        .collect::<Vec<String>>();
+
+    line_v.reverse();
+
+    line_v.join("\n")
+}
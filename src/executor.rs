@@ -1,35 +1,59 @@
-use std::{collections::HashSet, fs, path::Path, pin::Pin, sync::Arc};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
 
-use inc::inc_v_from_str;
+use error_stack::ResultExt;
 use tokio::sync::Mutex;
 
 use crate::{
-    def::{AsClassManager, AsSendSyncOption, AsSetable, Fu},
+    def::{AsClassManager, AsSendSyncOption, AsSetable, Edge, Fu, Pattern, Step},
     err,
-    util::{rs_2_str, str_2_rs},
-    ClassManager,
+    schema::Schema,
+    util::{self, rs_2_str, str_2_rs},
+    ClassManager, UndoLog,
 };
 
 mod inner;
 mod string;
 mod value_extractor;
 
-pub mod inc;
+pub mod config;
 pub mod def;
+pub mod dry_run;
+pub mod gc;
+pub mod id;
+pub mod inc;
+pub mod include;
+pub mod journal;
+pub mod limit;
+pub mod loader;
+pub mod policy;
+pub mod scope;
+pub mod trace;
 
 pub struct ClassExecutor<'cm, CM> {
     global_cm: &'cm mut CM,
     temp_cm: Arc<Mutex<ClassManager>>,
     path: String,
+    include: include::Include,
+    namespace: String,
+    frame_v: Vec<scope::Frame>,
+    signal: Option<def::Signal>,
+    trace_v: Vec<trace::Location>,
+    config: config::Config,
+    budget: Arc<limit::Budget>,
+    journal: Arc<Mutex<journal::Journal>>,
+    temp_log: Arc<Mutex<Vec<UndoLog>>>,
+    is_atomic: bool,
+    gc: Option<gc::Gc>,
 }
 
 impl<'cm, CM> ClassExecutor<'cm, CM> {
     pub fn new(global: &'cm mut CM) -> Self {
-        Self {
-            global_cm: global,
-            temp_cm: Arc::new(Mutex::new(ClassManager::new())),
-            path: ".".to_string(),
-        }
+        Self::new_with_temp(global, Arc::new(Mutex::new(ClassManager::new())))
     }
 
     pub fn new_with_temp(global: &'cm mut CM, temp_cm: Arc<Mutex<ClassManager>>) -> Self {
@@ -37,8 +61,93 @@ impl<'cm, CM> ClassExecutor<'cm, CM> {
             global_cm: global,
             temp_cm,
             path: ".".to_string(),
+            include: include::Include::default(),
+            namespace: String::new(),
+            frame_v: vec![],
+            signal: None,
+            trace_v: vec![],
+            config: config::Config::default(),
+            budget: Arc::new(limit::Budget::default()),
+            journal: Arc::new(Mutex::new(journal::Journal::default())),
+            temp_log: Arc::new(Mutex::new(vec![])),
+            is_atomic: false,
+            gc: None,
         }
     }
+
+    /// Replaces all the settings at once, as given to the executors of hooks.
+    pub fn with_config(mut self, config: config::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Budgets applied to every [`ClassExecutor::execute_script`].
+    pub fn with_limit(mut self, limit: limit::Limit) -> Self {
+        self.config.limit = limit;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: limit::CancelToken) -> Self {
+        self.config.cancel = cancel;
+        self
+    }
+
+    /// Restricts the builtins and global classes scripts can use, hooks included.
+    pub fn with_policy(mut self, policy: policy::Policy) -> Self {
+        self.config.policy = Arc::new(policy);
+        self
+    }
+
+    /// Validates the writes of scripts against `schema`, hooks included.
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.config.schema = Arc::new(schema);
+        self
+    }
+
+    /// Where objects get their ids, a sequential or seeded generator makes runs reproducible.
+    pub fn with_id_generator(mut self, id_generator: impl id::IdGenerator + 'static) -> Self {
+        self.config.id_generator = Arc::new(id_generator);
+        self
+    }
+
+    /// Runs every script in a transaction of the global manager, rolled back if it fails.
+    ///
+    /// A manager without transactions is rolled back from a [`journal::Journal`] of the
+    /// executor. `$` classes are not part of the transaction.
+    pub fn with_atomic(mut self, is_atomic: bool) -> Self {
+        self.is_atomic = is_atomic;
+        self
+    }
+
+    /// Collects unreachable objects after the scripts `gc` schedules.
+    pub fn with_gc(mut self, gc: gc::Gc) -> Self {
+        self.gc = Some(gc);
+        self
+    }
+
+    /// Directory searched by `#include` after the one of the including file.
+    pub fn with_search_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.config
+            .search_path_v
+            .push(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    /// Where `#include` reads scripts, the file system by default.
+    pub fn with_loader(mut self, loader: impl loader::ScriptLoader + 'static) -> Self {
+        self.config.set_loader(Arc::new(loader));
+        self
+    }
+
+    /// Confines `#include` to files below `root`, paths in scripts become relative to it.
+    ///
+    /// A loader of [`Self::with_loader`] keeps its scripts, but only to relative paths
+    /// that stay below its root.
+    pub fn with_sandbox(mut self, root: impl Into<PathBuf>) -> Self {
+        self.config.set_sandbox(root.into());
+        self.path = ".".to_string();
+        self
+    }
 }
 
 impl<'cm, CM: AsClassManager> ClassExecutor<'cm, CM> {
@@ -50,7 +159,53 @@ impl<'cm, CM: AsClassManager> ClassExecutor<'cm, CM> {
         'a: 'f,
         'a1: 'f,
     {
-        inner::execute_script(self, script)
+        self.budget = Arc::new(limit::Budget::new(
+            self.config.limit.clone(),
+            self.config.cancel.clone(),
+        ));
+
+        Box::pin(async move {
+            let rs = if !self.is_atomic {
+                let rs = inner::execute_file(self, "<script>", script).await?;
+
+                inner::take_return(self)?.unwrap_or(rs)
+            } else {
+                self.begin().await?;
+
+                let rs = match inner::execute_file(self, "<script>", script).await {
+                    Ok(rs) => inner::take_return(self).map(|op| op.unwrap_or(rs)),
+                    Err(e) => Err(e),
+                };
+
+                match rs {
+                    Ok(rs) => {
+                        self.commit().await?;
+
+                        rs
+                    }
+                    Err(e) => {
+                        if let Err(rollback_e) = self.rollback().await {
+                            log::error!("execute_script: rollback failed: {rollback_e:?}");
+                        }
+
+                        return Err(e);
+                    }
+                }
+            };
+
+            if let Some(gc) = self.gc.clone() {
+                if gc.tick() {
+                    // The result may hold objects the caller has yet to read.
+                    let mut root_v = gc.root_v().to_vec();
+
+                    root_v.extend(rs.iter().cloned());
+
+                    self.collect_garbage(root_v).await?;
+                }
+            }
+
+            Ok(rs)
+        })
     }
 }
 
@@ -61,6 +216,10 @@ impl<'cm, AsCM: AsClassManager> def::AsClassManagerHolder for ClassExecutor<'cm,
         self.temp_cm.clone()
     }
 
+    fn temp_mut(&mut self) -> &mut Arc<Mutex<ClassManager>> {
+        &mut self.temp_cm
+    }
+
     fn global_ref(&self) -> &Self::CM {
         self.global_cm
     }
@@ -72,9 +231,53 @@ impl<'cm, AsCM: AsClassManager> def::AsClassManagerHolder for ClassExecutor<'cm,
     fn path_mut(&mut self) -> &mut String {
         &mut self.path
     }
+
+    fn include_mut(&mut self) -> &mut include::Include {
+        &mut self.include
+    }
+
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn namespace_mut(&mut self) -> &mut String {
+        &mut self.namespace
+    }
+
+    fn frame_v(&self) -> &[scope::Frame] {
+        &self.frame_v
+    }
+
+    fn frame_v_mut(&mut self) -> &mut Vec<scope::Frame> {
+        &mut self.frame_v
+    }
+
+    fn signal_mut(&mut self) -> &mut Option<def::Signal> {
+        &mut self.signal
+    }
+
+    fn trace_v_mut(&mut self) -> &mut Vec<trace::Location> {
+        &mut self.trace_v
+    }
+
+    fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    fn budget(&self) -> Arc<limit::Budget> {
+        self.budget.clone()
+    }
+
+    fn journal(&self) -> Option<Arc<Mutex<journal::Journal>>> {
+        Some(self.journal.clone())
+    }
+
+    fn temp_log(&self) -> Option<Arc<Mutex<Vec<UndoLog>>>> {
+        Some(self.temp_log.clone())
+    }
 }
 
-impl<'cm, T, AsCM> AsClassManager for T
+impl<T, AsCM> AsClassManager for T
 where
     AsCM: AsClassManager,
     T: def::AsClassManagerHolder<CM = AsCM> + AsSendSyncOption,
@@ -90,7 +293,13 @@ where
         'a2: 'f,
     {
         Box::pin(async move {
-            if class.starts_with('$') {
+            if let Some((depth, class)) = scope::split_depth(class) {
+                if source.is_empty() {
+                    if let Some(target_v) = scope::lookup(self.frame_v(), depth, class) {
+                        return Ok(target_v.clone());
+                    }
+                }
+
                 let temp_mux = self.temp();
 
                 let temp = temp_mux.lock().await;
@@ -98,7 +307,7 @@ where
                 temp.get(class, source).await
             } else if class.starts_with('#') {
                 match class {
-                    "#fract" => Ok(vec![source.parse::<f64>().unwrap().fract().to_string()]),
+                    "#fract" => Ok(vec![parse_number::<f64>(source)?.fract().to_string()]),
                     "#dump" => {
                         let temp_mux = self.temp();
 
@@ -108,6 +317,144 @@ where
 
                         Ok(str_2_rs(&rj.to_string()))
                     }
+                    "#clone" => {
+                        let id_generator = self.id_generator();
+                        let temp_mux = self.temp();
+
+                        let mut temp = temp_mux.lock().await;
+
+                        temp.begin_log();
+
+                        let copy = temp.clone_object(source, &mut || id_generator.next_id());
+                        let log = temp.end_log();
+
+                        drop(temp);
+
+                        inner::log_temp(self, log).await;
+
+                        Ok(vec![copy])
+                    }
+                    "#equal" => {
+                        let left_v = self.get("$left", source).await?;
+                        let right_v = self.get("$right", source).await?;
+
+                        let temp_mux = self.temp();
+
+                        let temp = temp_mux.lock().await;
+
+                        let is_equal = left_v.len() == right_v.len()
+                            && left_v
+                                .iter()
+                                .zip(&right_v)
+                                .all(|(left, right)| temp.is_equal(left, right));
+
+                        Ok(if is_equal {
+                            vec!["1".to_string()]
+                        } else {
+                            vec![]
+                        })
+                    }
+                    "#diff" => {
+                        let left_v = self.get("$left", source).await?;
+                        let right_v = self.get("$right", source).await?;
+
+                        let temp_mux = self.temp();
+
+                        let temp = temp_mux.lock().await;
+
+                        let mut removed_v = left_v
+                            .iter()
+                            .flat_map(|left| temp.edge_v(left))
+                            .collect::<Vec<String>>();
+                        let mut rs = vec![];
+
+                        for edge in right_v.iter().flat_map(|right| temp.edge_v(right)) {
+                            match removed_v.iter().position(|removed| *removed == edge) {
+                                Some(pos) => {
+                                    removed_v.remove(pos);
+                                }
+                                None => rs.push(format!("+{edge}")),
+                            }
+                        }
+
+                        rs.extend(removed_v.into_iter().map(|edge| format!("-{edge}")));
+
+                        Ok(rs)
+                    }
+                    "#query" => {
+                        let pattern = Pattern {
+                            class: self.get("$class", source).await?.first().cloned(),
+                            source: self.get("$source", source).await?.first().cloned(),
+                            target: self.get("$target", source).await?.first().cloned(),
+                        };
+                        let select_v = self.get("$select", source).await?;
+
+                        let policy = self.policy();
+                        let edge_v = self
+                            .query(pattern)
+                            .await?
+                            .into_iter()
+                            // Not the fields of the pattern object itself.
+                            .filter(|(class, edge_source, _)| {
+                                edge_source != source && policy.check_read(class).is_ok()
+                            });
+
+                        let mut rs = vec![];
+
+                        match select_v.first().map(|s| s.as_str()) {
+                            Some(select @ ("class" | "source" | "target")) => {
+                                for (class, source, target) in edge_v {
+                                    let value = match select {
+                                        "class" => class,
+                                        "source" => source,
+                                        _ => target,
+                                    };
+
+                                    if !rs.contains(&value) {
+                                        rs.push(value);
+                                    }
+                                }
+                            }
+                            Some(select) => {
+                                return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                                    format!("#query: can not select '{select}', expected class, source or target")
+                                });
+                            }
+                            None => {
+                                let id_generator = self.id_generator();
+                                let temp_mux = self.temp();
+
+                                let mut temp = temp_mux.lock().await;
+
+                                temp.begin_log();
+
+                                let append_rs: err::Result<()> = async {
+                                    for (class, source, target) in edge_v {
+                                        let edge = id_generator.next_id();
+
+                                        temp.append("$class", &edge, vec![class]).await?;
+                                        temp.append("$source", &edge, vec![source]).await?;
+                                        temp.append("$target", &edge, vec![target]).await?;
+                                        temp.declare_object(&edge).await?;
+
+                                        rs.push(edge);
+                                    }
+
+                                    Ok(())
+                                }
+                                .await;
+                                let log = temp.end_log();
+
+                                drop(temp);
+
+                                inner::log_temp(self, log).await;
+
+                                append_rs?;
+                            }
+                        }
+
+                        Ok(rs)
+                    }
                     "#inner" => {
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
@@ -156,21 +503,28 @@ where
                         let to_v = self.get("$to", source).await?;
 
                         let from = match from_v.first() {
-                            Some(s) => s.parse().unwrap(),
+                            Some(s) => parse_number(s)?,
                             None => 0,
                         };
                         let to = match to_v.first() {
-                            Some(s) => s.parse().unwrap(),
+                            Some(s) => parse_number(s)?,
                             None => source_v.len(),
                         };
 
-                        Ok(source_v[from..to].iter().map(|s| s.clone()).collect())
+                        Ok(source_v
+                            .get(from..to)
+                            .ok_or(err::Error::RuntimeError)
+                            .attach_printable_lazy(|| {
+                                format!("#slice: {from}..{to} out of {}", source_v.len())
+                            })?
+                            .to_vec())
                     }
                     "#index" => {
                         let source_v = self.get("$source", source).await?;
                         let index_v = self.get("$index", source).await?;
 
-                        let index = index_v.first().unwrap().parse::<usize>().unwrap();
+                        let index =
+                            parse_number::<usize>(index_v.first().map_or("", |s| s.as_str()))?;
 
                         Ok(match source_v.get(index) {
                             Some(rs) => vec![rs.clone()],
@@ -194,33 +548,79 @@ where
                         Ok(rs)
                     }
                     "#source" => {
-                        let temp_mux = self.temp();
-
-                        let temp = temp_mux.lock().await;
                         let class_v = self.get("$class", source).await?;
+                        let target_v = self.get("$target", source).await?;
+
+                        let mut rs = vec![];
 
-                        if class_v[0].starts_with('$') {
-                            let target_v = self.get("$target", source).await?;
-                            let class_v = self.get("$class", source).await?;
+                        for class in &class_v {
+                            self.policy().check_read(class)?;
 
-                            Ok(temp
-                                .get_source(&target_v[0], &class_v[0])
-                                .unwrap_or_default())
+                            for target in &target_v {
+                                rs.extend(self.get_source(target, class).await?);
+                            }
+                        }
+
+                        Ok(rs)
+                    }
+                    "#reachable" | "#ancestors" | "#depth" => {
+                        let start_field = if class == "#reachable" {
+                            "$source"
                         } else {
-                            let data = temp.dump(source);
+                            "$target"
+                        };
+                        let class_v = self.get("$class", source).await?;
+                        let start_v = self.get(start_field, source).await?;
+
+                        let (Some(edge_class), Some(start)) = (class_v.first(), start_v.first())
+                        else {
+                            return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                                format!("{class}: {source} needs $class and {start_field}")
+                            });
+                        };
+
+                        self.policy().check_read(edge_class)?;
 
-                            self.global_ref().get(class, &data.to_string()).await
+                        match class {
+                            "#reachable" => self.reachable(edge_class, start).await,
+                            "#ancestors" => self.ancestors(edge_class, start).await,
+                            _ => Ok(vec![self.depth(edge_class, start).await?.to_string()]),
                         }
                     }
+                    "#path" => {
+                        let class_v = self.get("$class", source).await?;
+                        let from_v = self.get("$from", source).await?;
+                        let to_v = self.get("$to", source).await?;
+
+                        let (Some(class), Some(from), Some(to)) =
+                            (class_v.first(), from_v.first(), to_v.first())
+                        else {
+                            return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                                format!("#path: {source} needs $class, $from and $to")
+                            });
+                        };
+
+                        self.policy().check_read(class)?;
+
+                        self.path(class, from, to).await
+                    }
                     _ => {
                         let script_v = self.get("onget", class).await?;
 
                         if !script_v.is_empty() {
-                            let mut ce = ReadOnlyClassExecutor::new(self.global_ref());
+                            let mut ce = ReadOnlyClassExecutor::new(self.global_ref())
+                                .with_config(self.config().clone());
+
+                            ce.budget = self.budget();
+                            ce.namespace = self.namespace().to_string();
 
                             ce.append("$source", "", vec![source.to_string()]).await?;
 
-                            ce.execute_script(&rs_2_str(&script_v)).await
+                            let file = format!("onget({class})");
+                            let rs =
+                                inner::execute_file(&mut ce, &file, &rs_2_str(&script_v)).await?;
+
+                            Ok(inner::take_return(&mut ce)?.unwrap_or(rs))
                         } else {
                             self.global_ref().get(class, source).await
                         }
@@ -232,13 +632,12 @@ where
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
 
-                        let sz = left_v.len();
-
                         let mut rs = vec![];
 
-                        for i in 0..sz {
-                            let left = left_v[i].parse::<f64>().unwrap();
-                            let right = right_v[i].parse::<f64>().unwrap();
+                        for (i, left) in left_v.iter().enumerate() {
+                            let left = parse_number::<f64>(left)?;
+                            let right =
+                                parse_number::<f64>(right_v.get(i).map_or("", |s| s.as_str()))?;
 
                             rs.push((left + right).to_string());
                         }
@@ -249,13 +648,12 @@ where
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
 
-                        let sz = left_v.len();
-
                         let mut rs = vec![];
 
-                        for i in 0..sz {
-                            let left = left_v[i].parse::<f64>().unwrap();
-                            let right = right_v[i].parse::<f64>().unwrap();
+                        for (i, left) in left_v.iter().enumerate() {
+                            let left = parse_number::<f64>(left)?;
+                            let right =
+                                parse_number::<f64>(right_v.get(i).map_or("", |s| s.as_str()))?;
 
                             rs.push((left - right).to_string());
                         }
@@ -266,13 +664,12 @@ where
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
 
-                        let sz = left_v.len();
-
                         let mut rs = vec![];
 
-                        for i in 0..sz {
-                            let left = left_v[i].parse::<f64>().unwrap();
-                            let right = right_v[i].parse::<f64>().unwrap();
+                        for (i, left) in left_v.iter().enumerate() {
+                            let left = parse_number::<f64>(left)?;
+                            let right =
+                                parse_number::<f64>(right_v.get(i).map_or("", |s| s.as_str()))?;
 
                             rs.push((left * right).to_string());
                         }
@@ -283,13 +680,12 @@ where
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
 
-                        let sz = left_v.len();
-
                         let mut rs = vec![];
 
-                        for i in 0..sz {
-                            let left = left_v[i].parse::<f64>().unwrap();
-                            let right = right_v[i].parse::<f64>().unwrap();
+                        for (i, left) in left_v.iter().enumerate() {
+                            let left = parse_number::<f64>(left)?;
+                            let right =
+                                parse_number::<f64>(right_v.get(i).map_or("", |s| s.as_str()))?;
 
                             rs.push((left / right).to_string());
                         }
@@ -300,20 +696,46 @@ where
                         let left_v = self.get("$left", source).await?;
                         let right_v = self.get("$right", source).await?;
 
-                        let sz = left_v.len();
-
                         let mut rs = vec![];
 
-                        for i in 0..sz {
-                            let left = left_v[i].parse::<i32>().unwrap();
-                            let right = right_v[i].parse::<i32>().unwrap();
+                        for (i, left) in left_v.iter().enumerate() {
+                            let left = parse_number::<i32>(left)?;
+                            let right =
+                                parse_number::<i32>(right_v.get(i).map_or("", |s| s.as_str()))?;
+
+                            let rem = left
+                                .checked_rem(right)
+                                .ok_or(err::Error::RuntimeError)
+                                .attach_printable_lazy(|| {
+                                    format!("%: {left} % {right} is undefined")
+                                })?;
 
-                            rs.push((left % right).to_string());
+                            rs.push(rem.to_string());
                         }
 
                         Ok(rs)
                     }
-                    _ => self.global_ref().get(class, source).await,
+                    _ => {
+                        let namespace = self.namespace();
+
+                        // Included code reads the data of its host through unqualified classes:
+                        // `view` in `planet` is `planet::view`, `view` while that one is empty.
+                        // Writes are never redirected, so the host data can not be overwritten.
+                        if !namespace.is_empty() && !class.contains("::") {
+                            let rs = self
+                                .global_ref()
+                                .get(&qualify(namespace, class), source)
+                                .await?;
+
+                            if !rs.is_empty() {
+                                return Ok(rs);
+                            }
+
+                            log::debug!("{class}({source}): empty in {namespace}, read globally");
+                        }
+
+                        self.global_ref().get(class, source).await
+                    }
                 }
             }
         })
@@ -331,34 +753,70 @@ where
         'a2: 'f,
     {
         Box::pin(async move {
-            if class.starts_with('$') {
+            if let Some((depth, class)) = scope::split_depth(class) {
+                if source.is_empty() {
+                    if let Some(frame) = scope::frame_mut(self.frame_v_mut(), depth) {
+                        frame.remove(class, &target_v);
+
+                        return Ok(());
+                    }
+                }
+
                 let temp_mux = self.temp();
 
                 let mut temp = temp_mux.lock().await;
 
-                temp.remove(class, source, target_v).await
+                temp.begin_log();
+
+                let rs = temp.remove(class, source, target_v).await;
+                let log = temp.end_log();
+
+                drop(temp);
+
+                inner::log_temp(self, log).await;
+
+                rs
             } else if class.starts_with('#') {
                 let script_v = self.get("onremove", class).await?;
 
                 if !script_v.is_empty() {
-                    let mut ce = ClassExecutor::new(self.global_mut().unwrap());
+                    let budget = self.budget();
+                    let config = self.config().clone();
+                    let namespace = self.namespace().to_string();
+                    let journal = self.journal();
+                    let mut ce = ClassExecutor::new(self.writable_global(class, source)?)
+                        .with_config(config);
+
+                    ce.budget = budget;
+                    ce.namespace = namespace;
+
+                    if let Some(journal) = journal {
+                        ce.journal = journal;
+                    }
 
                     ce.append("$source", "", vec![source.to_string()]).await?;
                     ce.append("$target", "", target_v).await?;
 
-                    ce.execute_script(&rs_2_str(&script_v)).await?;
+                    let file = format!("onremove({class})");
+
+                    inner::execute_file(&mut ce, &file, &rs_2_str(&script_v)).await?;
+                    inner::take_return(&mut ce)?;
 
                     Ok(())
                 } else {
-                    self.global_mut()
-                        .unwrap()
+                    inner::record(self, class, source).await?;
+
+                    self.writable_global(class, source)?
                         .remove(class, source, target_v)
                         .await
                 }
             } else {
-                self.global_mut()
-                    .unwrap()
-                    .remove(class, source, target_v)
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                self.writable_global(&class, source)?
+                    .remove(&class, source, target_v)
                     .await
             }
         })
@@ -376,12 +834,29 @@ where
         'a2: 'f,
     {
         Box::pin(async move {
-            if class.starts_with('$') {
+            if let Some((depth, class)) = scope::split_depth(class) {
+                if source.is_empty() {
+                    if let Some(frame) = scope::frame_mut(self.frame_v_mut(), depth) {
+                        frame.append(class, target_v);
+
+                        return Ok(());
+                    }
+                }
+
                 let temp_mux = self.temp();
 
                 let mut temp = temp_mux.lock().await;
 
-                temp.append(class, source, target_v).await
+                temp.begin_log();
+
+                let rs = temp.append(class, source, target_v).await;
+                let log = temp.end_log();
+
+                drop(temp);
+
+                inner::log_temp(self, log).await;
+
+                rs
             } else if class.starts_with('#') {
                 match class {
                     "#switch" => {
@@ -406,9 +881,32 @@ where
                         Ok(())
                     }
                     "#loop" => {
-                        let inc_v = inc_v_from_str(&rs_2_str(&target_v))?;
+                        let inc_v = inner::parse(self, &rs_2_str(&target_v))?;
+                        let budget = self.budget();
+                        let mut iteration = 0;
+
+                        loop {
+                            iteration += 1;
 
-                        while !inner::execute(self, &inc_v).await?.is_empty() {}
+                            budget.iterate(iteration)?;
+
+                            let rs = inner::execute(self, &inc_v).await?;
+
+                            match self.signal_mut().take() {
+                                Some(def::Signal::Break) => break,
+                                Some(def::Signal::Continue) => continue,
+                                Some(signal) => {
+                                    *self.signal_mut() = Some(signal);
+
+                                    break;
+                                }
+                                None => {}
+                            }
+
+                            if rs.is_empty() {
+                                break;
+                            }
+                        }
 
                         Ok(())
                     }
@@ -420,6 +918,8 @@ where
                         let source_v = self.get("$source", source).await?;
                         let class_v = self.get("$class", source).await?;
 
+                        inner::check_write(self, &class_v[0])?;
+
                         let target_v =
                             inner::unwrap_value(self, &inc::IncVal::Object(target_v[0].clone()))
                                 .await?;
@@ -430,233 +930,2756 @@ where
                         log::debug!("#map: target_v = {target_v:?}");
                         let source_v = self.get("$source", source).await?;
                         let class_v = self.get("$class", source).await?;
+
+                        inner::check_write(self, &class_v[0])?;
+
                         let item_v = self.get("$source", &target_v[0]).await?;
                         let mapper_v = self.get("$mapper", &target_v[0]).await?;
 
                         let script = rs_2_str(&mapper_v);
 
                         log::debug!("#map: script = {mapper_v:?}");
-                        let inc_v = inc_v_from_str(&script)?;
+                        let inc_v = inner::parse(self, &script)?;
 
                         let mut rs = Vec::with_capacity(item_v.len());
-                        let mut index = 0;
 
-                        for item in item_v {
-                            self.append("$item", "", vec![item.clone()]).await?;
-                            self.append("$index", "", vec![index.to_string()]).await?;
+                        for (index, item) in item_v.into_iter().enumerate() {
+                            let mut frame = scope::Frame::default();
+
+                            frame.append("$item", vec![item]);
+                            frame.append("$index", vec![index.to_string()]);
 
-                            rs.push(inner::execute(self, &inc_v).await?.join("\n"));
+                            self.frame_v_mut().push(frame);
 
-                            self.remove("$item", "", vec![item]).await?;
-                            self.remove("$index", "", vec![index.to_string()]).await?;
+                            let item_rs = inner::execute(self, &inc_v).await;
 
-                            index += 1;
+                            self.frame_v_mut().pop();
+
+                            let item_rs = item_rs?;
+
+                            match self.signal_mut().take() {
+                                Some(def::Signal::Break) => break,
+                                Some(def::Signal::Continue) => continue,
+                                Some(signal) => {
+                                    *self.signal_mut() = Some(signal);
+
+                                    return Ok(());
+                                }
+                                None => rs.push(item_rs.join("\n")),
+                            }
                         }
 
                         self.append(&class_v[0], &source_v[0], rs).await
                     }
                     "#include" => {
+                        self.policy().check_include()?;
+
                         for target in &target_v {
+                            // "lib.class" or {$path: "lib.class", $as: lib}
+                            let (path, namespace) = match self.get("$path", target).await?.pop() {
+                                Some(path) => {
+                                    let namespace = match self.get("$as", target).await?.pop() {
+                                        Some(namespace) => namespace,
+                                        None => self.namespace().to_string(),
+                                    };
+
+                                    (path, namespace)
+                                }
+                                None => (target.clone(), self.namespace().to_string()),
+                            };
+
                             let root = self.path_mut().clone();
-                            let file_path = format!("{root}/{target}");
+                            let loader = self.loader();
+                            let search_path_v = self.config().search_path_v.clone();
+                            let key =
+                                include::resolve(loader.as_ref(), &search_path_v, &root, &path)?;
+                            let id = if namespace.is_empty() {
+                                key.clone()
+                            } else {
+                                format!("{key} as {namespace}")
+                            };
+
+                            if !self.include_mut().enter(&id)? {
+                                log::debug!("#include: {id} already included");
+
+                                continue;
+                            }
+
+                            log::debug!("#include: {id}");
 
-                            println!("#include: file_path = {file_path}");
+                            let rs = match loader.load(&key).await {
+                                Ok(script) => {
+                                    let dir = Path::new(&key)
+                                        .parent()
+                                        .map(|dir| dir.to_string_lossy().to_string())
+                                        .unwrap_or(root.clone());
 
-                            let file = Path::new(&file_path);
+                                    let outer_namespace =
+                                        std::mem::replace(self.namespace_mut(), namespace);
 
-                            let dir = file
-                                .parent()
-                                .as_ref()
-                                .unwrap()
-                                .to_str()
-                                .unwrap()
-                                .to_string();
-                            let script = fs::read_to_string(file).unwrap();
+                                    *self.path_mut() = dir;
+                                    self.frame_v_mut().push(scope::Frame::default());
 
-                            *self.path_mut() = dir;
-                            inner::execute_script(self, &script).await?;
+                                    let rs = inner::execute_file(self, &key, &script).await;
 
-                            *self.path_mut() = root;
+                                    self.frame_v_mut().pop();
+                                    *self.path_mut() = root;
+                                    *self.namespace_mut() = outer_namespace;
+
+                                    rs.and_then(|_| inner::take_return(self))
+                                }
+                                Err(e) => Err(e),
+                            };
+
+                            self.include_mut().leave(rs.is_ok());
+
+                            rs?;
                         }
 
                         Ok(())
                     }
-                    "#call" => {
-                        self.set("$source", "", vec![source.to_string()]).await?;
+                    "#proc" => {
+                        inner::check_write(self, "oncall:param")?;
+                        inner::check_write(self, "oncall")?;
 
-                        inner::execute_script(self, &rs_2_str(&target_v)).await?;
+                        for target in &target_v {
+                            let param_v = self.get("$param", target).await?;
+                            let body_v = self.get("$body", target).await?;
+
+                            if let Some(param) = param_v.iter().find(|p| !p.starts_with('$')) {
+                                return Err(err::Error::SyntaxError).attach_printable_lazy(|| {
+                                    format!(
+                                        "#proc: parameter '{param}' of {source} is not a '$' class"
+                                    )
+                                });
+                            }
+
+                            self.set("oncall:param", source, param_v).await?;
+                            self.set("oncall", source, body_v).await?;
+                        }
 
                         Ok(())
                     }
-                    _ => {
-                        let script_v = self.get("onappend", class).await?;
+                    "#call" => {
+                        let mut frame = scope::Frame::default();
 
-                        if !script_v.is_empty() {
-                            let mut ce = ClassExecutor::new(self.global_mut().unwrap());
+                        frame.append("$source", vec![source.to_string()]);
 
-                            ce.append("$source", "", vec![source.to_string()]).await?;
-                            ce.append("$target", "", target_v).await?;
+                        self.frame_v_mut().push(frame);
 
-                            ce.execute_script(&rs_2_str(&script_v)).await?;
+                        let file = format!("#call({source})");
+                        let rs = inner::execute_file(self, &file, &rs_2_str(&target_v)).await;
 
-                            Ok(())
-                        } else {
-                            self.global_mut()
-                                .unwrap()
-                                .append(class, source, target_v)
-                                .await
+                        let frame = self.frame_v_mut().pop().unwrap();
+                        let rs = rs?;
+
+                        // The script returns through `$result` of the caller.
+                        if let Some(rs) = inner::take_return(self)? {
+                            self.set("$result", "", rs).await?;
+                        } else if frame.get("$result").is_some() {
+                            self.set("$result", "", rs).await?;
                         }
+
+                        Ok(())
                     }
-                }
-            } else {
-                self.global_mut()
-                    .unwrap()
-                    .append(class, source, target_v)
-                    .await
-            }
-        })
-    }
-}
+                    "#move" => {
+                        for target in &target_v {
+                            let class = self.get("$class", target).await?;
+                            let source = self.get("$source", target).await?;
+                            let from = self.get("$from", target).await?;
+                            let to = self.get("$to", target).await?;
+
+                            let (Some(class), Some(source), Some(from), Some(to)) =
+                                (class.first(), source.first(), from.first(), to.first())
+                            else {
+                                return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                                    format!("#move: {target} needs $class, $source, $from and $to")
+                                });
+                            };
+
+                            self.policy().check_write(class)?;
+
+                            self.move_to(class, source, parse_number(from)?, parse_number(to)?)
+                                .await?;
+                        }
 
-pub struct ReadOnlyClassExecutor<'cm, CM> {
-    global_cm: &'cm CM,
-    temp_cm: Arc<Mutex<ClassManager>>,
-    path: String,
-}
+                        Ok(())
+                    }
+                    "#delete" => {
+                        let is_cascade = match source {
+                            "" => false,
+                            "cascade" => true,
+                            _ => {
+                                return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                                    format!("#delete: unknown mode '{source}', expected 'cascade'")
+                                });
+                            }
+                        };
 
-impl<'cm, CM> ReadOnlyClassExecutor<'cm, CM> {
-    pub fn new(global: &'cm CM) -> Self {
-        Self {
-            global_cm: global,
-            temp_cm: Arc::new(Mutex::new(ClassManager::new())),
+                        let policy = self.policy();
+
+                        for target in &target_v {
+                            if policy.restricts_write() {
+                                for (class, _) in
+                                    inner::delete_key_v(self, target, is_cascade).await?
+                                {
+                                    policy.check_write(&class)?;
+                                }
+                            }
+
+                            self.delete(target, is_cascade).await?;
+                        }
+
+                        Ok(())
+                    }
+                    "#gc" => {
+                        let policy = self.policy();
+                        let live_v = self.mark(target_v).await?;
+
+                        if policy.restricts_write() {
+                            for (class, _) in inner::sweep_key_v(self, &live_v).await? {
+                                policy.check_write(&class)?;
+                            }
+                        }
+
+                        self.sweep(live_v).await?;
+
+                        Ok(())
+                    }
+                    "#atomic" => {
+                        // Objects live in the temp manager, which other executors may share:
+                        // the block logs its own changes to it and undoes just those on failure.
+                        let temp_log = self.temp_log();
+
+                        self.begin().await?;
+
+                        if let Some(temp_log) = &temp_log {
+                            temp_log.lock().await.push(UndoLog::default());
+                        }
+
+                        let rs = inner::execute_script(self, &rs_2_str(&target_v)).await;
+
+                        let log = match &temp_log {
+                            Some(temp_log) => temp_log.lock().await.pop(),
+                            None => None,
+                        };
+
+                        match rs {
+                            Ok(_) => {
+                                if let Some(log) = log {
+                                    inner::log_temp(self, log).await;
+                                }
+
+                                self.commit().await
+                            }
+                            Err(e) => {
+                                if let Some(log) = log {
+                                    self.temp().lock().await.undo(log);
+                                }
+
+                                if let Err(rollback_e) = self.rollback().await {
+                                    log::error!("#atomic: rollback failed: {rollback_e:?}");
+                                }
+
+                                Err(e)
+                            }
+                        }
+                    }
+                    "#try" => {
+                        for target in &target_v {
+                            let body_v = self.get("$body", target).await?;
+                            let catch_v = self.get("$catch", target).await?;
+
+                            let e = match inner::execute_script(self, &rs_2_str(&body_v)).await {
+                                Ok(_) => continue,
+                                Err(e) => e,
+                            };
+
+                            // Budgets can not be caught, or a script could run forever.
+                            if matches!(
+                                e.current_context(),
+                                err::Error::LimitExceeded
+                                    | err::Error::Timeout
+                                    | err::Error::Cancelled
+                            ) {
+                                return Err(e);
+                            }
+
+                            log::debug!("#try: caught {e:?}");
+
+                            *self.signal_mut() = None;
+
+                            let error = self.id_generator().next_id();
+
+                            self.append("$kind", &error, vec![e.current_context().kind()])
+                                .await?;
+                            self.append("$message", &error, vec![err::message(&e)])
+                                .await?;
+
+                            let mut frame = scope::Frame::default();
+
+                            frame.append("$error", vec![error.clone()]);
+
+                            self.frame_v_mut().push(frame);
+
+                            let rs = inner::execute_script(self, &rs_2_str(&catch_v)).await;
+
+                            self.frame_v_mut().pop();
+
+                            // The error only lives as long as its handler.
+                            let temp_mux = self.temp();
+                            let mut temp = temp_mux.lock().await;
+
+                            temp.begin_log();
+
+                            let delete_rs = temp.delete(&error, false).await;
+                            let log = temp.end_log();
+
+                            drop(temp);
+
+                            inner::log_temp(self, log).await;
+
+                            delete_rs?;
+                            rs?;
+                        }
+
+                        Ok(())
+                    }
+                    "#throw" => {
+                        let kind = if source.is_empty() { "Other" } else { source };
+
+                        Err(err::Error::Other(kind.to_string()))
+                            .attach_printable(rs_2_str(&target_v))
+                    }
+                    "#return" => {
+                        *self.signal_mut() = Some(def::Signal::Return(target_v));
+
+                        Ok(())
+                    }
+                    "#break" => {
+                        *self.signal_mut() = Some(def::Signal::Break);
+
+                        Ok(())
+                    }
+                    "#continue" => {
+                        *self.signal_mut() = Some(def::Signal::Continue);
+
+                        Ok(())
+                    }
+                    _ => {
+                        let script_v = self.get("onappend", class).await?;
+
+                        if !script_v.is_empty() {
+                            let budget = self.budget();
+                            let config = self.config().clone();
+                            let namespace = self.namespace().to_string();
+                            let journal = self.journal();
+                            let mut ce = ClassExecutor::new(self.writable_global(class, source)?)
+                                .with_config(config);
+
+                            ce.budget = budget;
+                            ce.namespace = namespace;
+
+                            if let Some(journal) = journal {
+                                ce.journal = journal;
+                            }
+
+                            ce.append("$source", "", vec![source.to_string()]).await?;
+                            ce.append("$target", "", target_v).await?;
+
+                            let file = format!("onappend({class})");
+
+                            inner::execute_file(&mut ce, &file, &rs_2_str(&script_v)).await?;
+                            inner::take_return(&mut ce)?;
+
+                            Ok(())
+                        } else {
+                            inner::record(self, class, source).await?;
+
+                            self.writable_global(class, source)?
+                                .append(class, source, target_v)
+                                .await
+                        }
+                    }
+                }
+            } else {
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                self.writable_global(&class, source)?
+                    .append(&class, source, target_v)
+                    .await
+            }
+        })
+    }
+
+    fn compare_and_set<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        expected_v: Vec<String>,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<bool>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if scope::split_depth(class).is_none() && !class.starts_with('#') {
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                return self
+                    .writable_global(&class, source)?
+                    .compare_and_set(&class, source, expected_v, target_v)
+                    .await;
+            }
+
+            // Locals and hooks are private to the executor, no one can interleave.
+            let current_v = self.get(class, source).await?;
+
+            if current_v != expected_v {
+                return Ok(false);
+            }
+
+            self.set(class, source, target_v).await?;
+
+            Ok(true)
+        })
+    }
+
+    fn insert<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if scope::split_depth(class).is_none() && !class.starts_with('#') {
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                return self
+                    .writable_global(&class, source)?
+                    .insert(&class, source, index, target_v)
+                    .await;
+            }
+
+            let mut list = self.get(class, source).await?;
+
+            util::insert_at(&mut list, index, target_v)?;
+
+            self.set(class, source, list).await
+        })
+    }
+
+    fn remove_at<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if scope::split_depth(class).is_none() && !class.starts_with('#') {
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                return self
+                    .writable_global(&class, source)?
+                    .remove_at(&class, source, index)
+                    .await;
+            }
+
+            let mut list = self.get(class, source).await?;
+
+            let target = util::remove_at(&mut list, index)?;
+
+            self.set(class, source, list).await?;
+
+            Ok(target)
+        })
+    }
+
+    fn move_to<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        from: usize,
+        to: usize,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if scope::split_depth(class).is_none() && !class.starts_with('#') {
+                let class = qualify(self.namespace(), class);
+
+                inner::record(self, &class, source).await?;
+
+                return self
+                    .writable_global(&class, source)?
+                    .move_to(&class, source, from, to)
+                    .await;
+            }
+
+            let mut list = self.get(class, source).await?;
+
+            util::move_to(&mut list, from, to)?;
+
+            self.set(class, source, list).await
+        })
+    }
+
+    /// Fields of an object may live in both managers, both delete it.
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            // A read-only executor must fail before the temp manager is touched.
+            self.writable_global("#delete", source)?;
+
+            let temp_mux = self.temp();
+
+            let mut temp = temp_mux.lock().await;
+
+            temp.begin_log();
+
+            let delete_rs = temp.delete(source, is_cascade).await;
+            let log = temp.end_log();
+
+            drop(temp);
+
+            inner::log_temp(self, log).await;
+
+            let mut deleted_v = delete_rs?;
+
+            if inner::is_journaled(self).await {
+                for (class, key_source) in inner::delete_key_v(self, source, is_cascade).await? {
+                    inner::record(self, &class, &key_source).await?;
+                }
+            }
+
+            for deleted in self
+                .writable_global("#delete", source)?
+                .delete(source, is_cascade)
+                .await?
+            {
+                if !deleted_v.contains(&deleted) {
+                    deleted_v.push(deleted);
+                }
+            }
+
+            Ok(deleted_v)
+        })
+    }
+
+    /// Plain classes are looked up in the namespace first, as [`AsClassManager::get`] does.
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if let Some((_, class)) = scope::split_depth(class) {
+                let temp_mux = self.temp();
+
+                let temp = temp_mux.lock().await;
+
+                return Ok(temp.get_source(target, class).unwrap_or_default());
+            }
+
+            let namespace = self.namespace();
+
+            if !namespace.is_empty() && !class.contains("::") && !class.starts_with('#') {
+                let rs = self
+                    .global_ref()
+                    .get_source(target, &qualify(namespace, class))
+                    .await?;
+
+                if !rs.is_empty() {
+                    return Ok(rs);
+                }
+            }
+
+            self.global_ref().get_source(target, class).await
+        })
+    }
+
+    /// Plain classes are looked up in the namespace first, as [`AsClassManager::get`] does.
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if let Some((_, class)) = scope::split_depth(class) {
+                let temp_mux = self.temp();
+
+                let temp = temp_mux.lock().await;
+
+                return temp.traverse(class, start, is_reverse).await;
+            }
+
+            let namespace = self.namespace();
+
+            if !namespace.is_empty() && !class.contains("::") && !class.starts_with('#') {
+                let rs = self
+                    .global_ref()
+                    .traverse(&qualify(namespace, class), start, is_reverse)
+                    .await?;
+
+                if !rs.is_empty() {
+                    return Ok(rs);
+                }
+            }
+
+            self.global_ref().traverse(class, start, is_reverse).await
+        })
+    }
+
+    /// `$` classes are in the temp manager, the others in the global one, both if unbound.
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let temp_mux = self.temp();
+
+            match pattern.class.as_deref() {
+                Some(class) => match scope::split_depth(class) {
+                    Some((_, class)) => {
+                        let pattern = Pattern {
+                            class: Some(class.to_string()),
+                            ..pattern
+                        };
+
+                        temp_mux.lock().await.query(pattern).await
+                    }
+                    None if class.starts_with('#') || self.namespace().is_empty() => {
+                        self.global_ref().query(pattern).await
+                    }
+                    None => {
+                        let rs = self
+                            .global_ref()
+                            .query(Pattern {
+                                class: Some(qualify(self.namespace(), class)),
+                                ..pattern.clone()
+                            })
+                            .await?;
+
+                        if !rs.is_empty() {
+                            return Ok(rs);
+                        }
+
+                        self.global_ref().query(pattern).await
+                    }
+                },
+                None => {
+                    let mut rs = temp_mux.lock().await.query(pattern.clone()).await?;
+
+                    rs.extend(self.global_ref().query(pattern).await?);
+
+                    Ok(rs)
+                }
+            }
+        })
+    }
+
+    /// Marks across both managers until no more objects are found, locals are roots too.
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        inner::declare_object(self, source, true)
+    }
+
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let mut object_v = self.temp().lock().await.object_v().await?;
+
+            object_v.extend(self.global_ref().object_v().await?);
+
+            Ok(object_v)
+        })
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let mut live_set = HashSet::new();
+
+            live_set.extend(root_v);
+            live_set.extend(self.frame_v().iter().flat_map(|f| f.target_v()).cloned());
+
+            loop {
+                let count = live_set.len();
+                let live_v = live_set.iter().cloned().collect::<Vec<String>>();
+
+                live_set.extend(self.temp().lock().await.mark(live_v.clone()).await?);
+                live_set.extend(self.global_ref().mark(live_v).await?);
+
+                if live_set.len() == count {
+                    break;
+                }
+            }
+
+            Ok(live_set.into_iter().collect())
+        })
+    }
+
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.writable_global("#gc", "")?;
+
+            let temp_mux = self.temp();
+
+            let mut temp = temp_mux.lock().await;
+
+            temp.begin_log();
+
+            let sweep_rs = temp.sweep(live_v.clone()).await;
+            let log = temp.end_log();
+
+            drop(temp);
+
+            inner::log_temp(self, log).await;
+
+            let mut garbage_v = sweep_rs?;
+
+            if inner::is_journaled(self).await {
+                for (class, source) in inner::sweep_key_v(self, &live_v).await? {
+                    inner::record(self, &class, &source).await?;
+                }
+            }
+
+            garbage_v.extend(self.writable_global("#gc", "")?.sweep(live_v).await?);
+
+            Ok(garbage_v)
+        })
+    }
+
+    /// Transactions cover the global manager, a read-only one has nothing to cover.
+    ///
+    /// A manager without transactions is covered by the journal of the executor, from its
+    /// outermost transaction on.
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let journal = self.journal();
+
+            let Some(global) = self.global_mut() else {
+                return Ok(());
+            };
+
+            if let Some(journal) = &journal {
+                let mut journal = journal.lock().await;
+
+                if journal.is_active() {
+                    journal.begin();
+
+                    return Ok(());
+                }
+            }
+
+            match (global.begin().await, journal) {
+                (Err(e), Some(journal))
+                    if matches!(e.current_context(), err::Error::Unsupported) =>
+                {
+                    journal.lock().await.begin();
+
+                    Ok(())
+                }
+                (rs, _) => rs,
+            }
+        })
+    }
+
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            if let Some(journal) = self.journal() {
+                let mut journal = journal.lock().await;
+
+                if journal.is_active() {
+                    journal.commit();
+
+                    return Ok(());
+                }
+            }
+
+            match self.global_mut() {
+                Some(global) => global.commit().await,
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let undo_v = match self.journal() {
+                Some(journal) => journal.lock().await.rollback(),
+                None => None,
+            };
+
+            match (self.global_mut(), undo_v) {
+                (Some(global), Some(undo_v)) => {
+                    for (class, source, target_v) in undo_v {
+                        global.set(&class, &source, target_v).await?;
+                    }
+
+                    Ok(())
+                }
+                (Some(global), None) => global.rollback().await,
+                (None, _) => Ok(()),
+            }
+        })
+    }
+}
+
+pub struct ReadOnlyClassExecutor<'cm, CM> {
+    global_cm: &'cm CM,
+    temp_cm: Arc<Mutex<ClassManager>>,
+    path: String,
+    include: include::Include,
+    namespace: String,
+    frame_v: Vec<scope::Frame>,
+    signal: Option<def::Signal>,
+    trace_v: Vec<trace::Location>,
+    config: config::Config,
+    budget: Arc<limit::Budget>,
+}
+
+impl<'cm, CM> ReadOnlyClassExecutor<'cm, CM> {
+    pub fn new(global: &'cm CM) -> Self {
+        Self::new_with_temp(global, Arc::new(Mutex::new(ClassManager::new())))
+    }
+
+    pub fn new_with_temp(global: &'cm CM, temp_cm: Arc<Mutex<ClassManager>>) -> Self {
+        Self {
+            global_cm: global,
+            temp_cm,
             path: ".".to_string(),
+            include: include::Include::default(),
+            namespace: String::new(),
+            frame_v: vec![],
+            signal: None,
+            trace_v: vec![],
+            config: config::Config::default(),
+            budget: Arc::new(limit::Budget::default()),
         }
     }
 
-    pub fn new_with_temp(global: &'cm CM, temp_cm: Arc<Mutex<ClassManager>>) -> Self {
-        Self {
-            global_cm: global,
-            temp_cm,
-            path: ".".to_string(),
+    /// Replaces all the settings at once, as given to the executors of hooks.
+    pub fn with_config(mut self, config: config::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Budgets applied to every [`ReadOnlyClassExecutor::execute_script`].
+    pub fn with_limit(mut self, limit: limit::Limit) -> Self {
+        self.config.limit = limit;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel: limit::CancelToken) -> Self {
+        self.config.cancel = cancel;
+        self
+    }
+
+    /// Restricts the builtins and global classes scripts can use, hooks included.
+    pub fn with_policy(mut self, policy: policy::Policy) -> Self {
+        self.config.policy = Arc::new(policy);
+        self
+    }
+
+    /// Validates the writes of scripts against `schema`, hooks included.
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.config.schema = Arc::new(schema);
+        self
+    }
+
+    /// Where objects get their ids, a sequential or seeded generator makes runs reproducible.
+    pub fn with_id_generator(mut self, id_generator: impl id::IdGenerator + 'static) -> Self {
+        self.config.id_generator = Arc::new(id_generator);
+        self
+    }
+
+    /// Directory searched by `#include` after the one of the including file.
+    pub fn with_search_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.config
+            .search_path_v
+            .push(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    /// Where `#include` reads scripts, the file system by default.
+    pub fn with_loader(mut self, loader: impl loader::ScriptLoader + 'static) -> Self {
+        self.config.set_loader(Arc::new(loader));
+        self
+    }
+
+    /// Confines `#include` to files below `root`, paths in scripts become relative to it.
+    ///
+    /// A loader of [`Self::with_loader`] keeps its scripts, but only to relative paths
+    /// that stay below its root.
+    pub fn with_sandbox(mut self, root: impl Into<PathBuf>) -> Self {
+        self.config.set_sandbox(root.into());
+        self.path = ".".to_string();
+        self
+    }
+}
+
+impl<'cm, AsCM: AsClassManager> def::AsClassManagerHolder for ReadOnlyClassExecutor<'cm, AsCM> {
+    type CM = AsCM;
+
+    fn temp(&self) -> Arc<Mutex<ClassManager>> {
+        self.temp_cm.clone()
+    }
+
+    fn temp_mut(&mut self) -> &mut Arc<Mutex<ClassManager>> {
+        &mut self.temp_cm
+    }
+
+    fn global_ref(&self) -> &Self::CM {
+        self.global_cm
+    }
+
+    fn global_mut(&mut self) -> Option<&mut Self::CM> {
+        None
+    }
+
+    fn path_mut(&mut self) -> &mut String {
+        &mut self.path
+    }
+
+    fn include_mut(&mut self) -> &mut include::Include {
+        &mut self.include
+    }
+
+    fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn namespace_mut(&mut self) -> &mut String {
+        &mut self.namespace
+    }
+
+    fn frame_v(&self) -> &[scope::Frame] {
+        &self.frame_v
+    }
+
+    fn frame_v_mut(&mut self) -> &mut Vec<scope::Frame> {
+        &mut self.frame_v
+    }
+
+    fn signal_mut(&mut self) -> &mut Option<def::Signal> {
+        &mut self.signal
+    }
+
+    fn trace_v_mut(&mut self) -> &mut Vec<trace::Location> {
+        &mut self.trace_v
+    }
+
+    fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    fn budget(&self) -> Arc<limit::Budget> {
+        self.budget.clone()
+    }
+}
+
+impl<'cm, CM: AsClassManager> ReadOnlyClassExecutor<'cm, CM> {
+    pub fn execute_script<'a, 'a1, 'f>(
+        &'a mut self,
+        script: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        self.budget = Arc::new(limit::Budget::new(
+            self.config.limit.clone(),
+            self.config.cancel.clone(),
+        ));
+
+        Box::pin(async move {
+            let rs = inner::execute_file(self, "<script>", script).await?;
+
+            Ok(inner::take_return(self)?.unwrap_or(rs))
+        })
+    }
+}
+
+/// `view` in namespace `planet` is `planet::view`, qualified classes, locals and builtins are kept.
+fn qualify(namespace: &str, class: &str) -> String {
+    if namespace.is_empty()
+        || class.contains("::")
+        || scope::split_depth(class).is_some()
+        || policy::is_builtin(class)
+    {
+        class.to_string()
+    } else {
+        format!("{namespace}::{class}")
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> err::Result<T> {
+    s.parse()
+        .map_err(|_| err::Error::RuntimeError)
+        .attach_printable_lazy(|| format!("'{s}' is not a number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        schema::{ClassDef, ValueType},
+        ClassManager,
+    };
+
+    use super::*;
+
+    /// Runs `fu` to completion on a multi-threaded runtime, with logs enabled.
+    fn block_on<F: std::future::Future>(fu: F) -> F::Output {
+        let _ =
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
+                .is_test(true)
+                .try_init();
+
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(fu)
+    }
+
+    #[test]
+    fn test_return() {
+        block_on(async {
+            log::debug!("start");
+
+            let mut cm = ClassManager::new();
+
+            let rs = ClassExecutor::new(&mut cm)
+                .execute_script(
+                    "test = test(test);
+                    test(test) = $result();",
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs.len(), 1);
+            assert_eq!(rs[0], "test");
+        })
+    }
+
+    #[test]
+    fn test_add() {
+        block_on(async {
+            log::debug!("start");
+
+            let mut cm = ClassManager::new();
+
+            let rs = ClassExecutor::new(&mut cm)
+                .execute_script(
+                    "1 = $left(test);
+                    1 = $right(test);
+                    +(test) = $result();",
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs.len(), 1);
+            assert_eq!(rs[0], "2");
+        })
+    }
+
+    #[test]
+    fn test_obj() {
+        block_on(async {
+            log::debug!("start");
+
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+1 = $sum();
+2 = $pos();
+
+<
+    +(@{
+        $left: $sum(),
+        $right: $pos()
+    }) := $sum();
+
+    +(@{
+        $left: $pos(),
+        $right: 1
+    }) := $pos();
+
+    [
+        @{
+            $case: <#inner(@{$left: 101, $right: $pos()})>,
+            $then: <[] := $result();>
+        },
+        @{$case: <1>, $then: <1 := $result();>}
+    ] = #switch();
+> = #loop();
+
+$sum() := $result();
+             "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs.len(), 1);
+            assert_eq!(rs[0], "5050");
+        })
+    }
+
+    #[test]
+    fn test_template() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+value := $value();
+<${$value()}> := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs[0], "value")
+        });
+    }
+
+    #[test]
+    fn test_limit() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let e = ClassExecutor::new(&mut cm)
+                .with_limit(limit::Limit {
+                    max_loop: Some(10),
+                    ..Default::default()
+                })
+                .execute_script("<1 := $result();> = #loop();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::LimitExceeded));
+
+            let e = ClassExecutor::new(&mut cm)
+                .with_limit(limit::Limit {
+                    max_step: Some(100),
+                    ..Default::default()
+                })
+                .execute_script("<1 := $result();> = #loop();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::LimitExceeded));
+
+            let e = ClassExecutor::new(&mut cm)
+                .with_limit(limit::Limit {
+                    timeout: Some(std::time::Duration::from_millis(50)),
+                    ..Default::default()
+                })
+                .execute_script("<1 := $result();> = #loop();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::Timeout));
+
+            let cancel = limit::CancelToken::new();
+
+            cancel.cancel();
+
+            let e = ClassExecutor::new(&mut cm)
+                .with_cancel_token(cancel)
+                .execute_script("1 := $result();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::Cancelled));
+
+            // Hooks spend the budget of the script firing them, and can not catch running out.
+            ClassExecutor::new(&mut cm)
+                .execute_script(
+                    r#"
+<{$body: <<1 := $result();> = #loop();>, $catch: <>} = #try();> = onappend(#spin);
+<<1 := $result();> = #loop();> = onget(#spin);
+                "#,
+                )
+                .await
+                .unwrap();
+
+            for script in ["1 = #spin();", "#spin() = $result();"] {
+                let e = ClassExecutor::new(&mut cm)
+                    .with_limit(limit::Limit {
+                        max_loop: Some(10),
+                        ..Default::default()
+                    })
+                    .execute_script(script)
+                    .await
+                    .unwrap_err();
+
+                assert!(matches!(e.current_context(), err::Error::LimitExceeded));
+            }
+        });
+    }
+
+    #[test]
+    fn test_scope() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+caller = $source();
+0 = $outer();
+
+[1, 2] = $list();
+
+{
+    $source: $list(),
+    $mapper: <
+        $item() := $outer();
+        $item() := ^$last();
+        $item() := $result();
+    >
+} = #map({
+    $source: "",
+    $class: $mapped
+});
+
+<$source() := $result();> = #call(callee);
+
+[$source(), $outer(), $last(), $mapped(), $result()] := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["caller", "0", "2", "1", "2", "callee"]);
+        });
+    }
+
+    #[test]
+    fn test_proc() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+{
+    $param: [$a, $b],
+    $body: <
+        +({$left: $a(), $right: $b()}) := $result();
+    >
+} = #proc(add);
+
+caller = $a();
+caller := $result();
+
+add({$a: 1, $b: 2}) = $sum();
+add({$a: $sum(), $b: 3}) = $sum();
+
+[$sum(), $a(), $result()] := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["3", "6", "caller", "caller"]);
+
+            let e = ce.execute_script("1 = add(x);").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+
+            let e = ce.execute_script("{add: 1} = $o();").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+            assert!(cm.get("add", "x").await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_control_flow() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+0 = $sum();
+0 = $pos();
+
+<
+    +({$left: $pos(), $right: 1}) := $pos();
+
+    [
+        {$case: <#inner({$left: 101, $right: $pos()})>, $then: <= #break();>},
+        {$case: <#inner({$left: 0, $right: %({$left: $pos(), $right: 2})})>, $then: <= #continue();>}
+    ] = #switch();
+
+    +({$left: $sum(), $right: $pos()}) := $sum();
+    1 := $result();
+> = #loop();
+
+{
+    $param: [$limit],
+    $body: <
+        0 := $i();
+
+        <
+            +({$left: $i(), $right: 1}) := $i();
+
+            [
+                {$case: <#inner({$left: $limit(), $right: $i()})>, $then: <$i() = #return();>}
+            ] = #switch();
+
+            1 := $result();
+        > = #loop();
+
+        never := $result();
+    >
+} = #proc(count_to);
+
+[$sum(), count_to({$limit: 7})] = #return();
+
+never := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["2500", "7"]);
+
+            let e = ce.execute_script("= #break();").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+        });
+    }
+
+    #[test]
+    fn test_try() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+{
+    $body: <+({$left: 1, $right: one}) = $sum();>,
+    $catch: <$kind($error()) = ^$kind();>
+} = #try();
+
+{
+    $body: <"missing.class" = #include();>,
+    $catch: <$kind($error()) = ^$kind();>
+} = #try();
+
+{
+    $body: <
+        1 = $before();
+        "no more coffee" = #throw(Empty);
+        1 = $after();
+    >,
+    $catch: <
+        $kind($error()) = ^$kind();
+        $message($error()) = ^$message();
+    >
+} = #try();
+
+[$kind(), $message(), $before(), $after()] := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                rs,
+                ["RuntimeError", "NotFound", "Empty", "no more coffee", "1"]
+            );
+
+            let e = ce
+                .execute_script("{$body: <= #throw();>, $catch: <= #throw(Again);>} = #try();")
+                .await
+                .unwrap_err();
+
+            assert_eq!(e.current_context().kind(), "Again");
+
+            let rs = ce
+                .execute_script(
+                    r#"
+{
+    $body: <%({$left: 1, $right: 0}) = $rem();>,
+    $catch: <$kind($error()) := ^$rem();>
+} = #try();
+{
+    $body: <%({$left: -2147483648, $right: -1}) = $min();>,
+    $catch: <$kind($error()) := ^$min();>
+} = #try();
+
+[$rem(), $min()] := $result();
+            "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["RuntimeError", "RuntimeError"]);
+
+            ce.execute_script(
+                r#"
+0 = $i();
+<
+    {$body: <= #throw();>, $catch: <$kind($error()) = ^$last();>} = #try();
+    +({$left: $i(), $right: 1}) := $i();
+    #inner({$left: $i(), $right: [1, 2, 3]}) := $result();
+> = #loop();
+            "#,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(ce.get("$last", "").await.unwrap(), ["Other"; 4]);
+            assert!(!ce
+                .temp_cm
+                .lock()
+                .await
+                .query(Pattern {
+                    class: Some("$kind".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .iter()
+                .any(|(_, source, _)| util::is_object_id(source)));
+        });
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("moon_class_{}", uuid::Uuid::new_v4()));
+        let lib_dir = dir.join("lib");
+
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(lib_dir.join("counter.class"), "1 = count(lib);").unwrap();
+        fs::write(
+            dir.join("main.class"),
+            "[\"counter.class\", \"counter.class\"] = #include();",
+        )
+        .unwrap();
+        fs::write(dir.join("a.class"), "\"b.class\" = #include();").unwrap();
+        fs::write(dir.join("b.class"), "\"a.class\" = #include();").unwrap();
+
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm).with_search_path(&lib_dir);
+
+            ce.path = dir.to_string_lossy().to_string();
+
+            let rs = ce
+                .execute_script(
+                    r#"
+["main.class", "counter.class"] = #include();
+count(lib) := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["1"]);
+
+            let e = ce
+                .execute_script("\"a.class\" = #include();")
+                .await
+                .unwrap_err();
+
+            let a = dir.join("a.class").canonicalize().unwrap();
+            let b = dir.join("b.class").canonicalize().unwrap();
+
+            assert!(err::message(&e).ends_with(&format!(
+                "cycle {} -> {} -> {}",
+                a.display(),
+                b.display(),
+                a.display()
+            )));
+        });
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_loader() {
+        block_on(async {
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert("main.class", "\"lib/util.class\" = #include();");
+            loader.insert("lib/util.class", "\"../value.class\" = #include();");
+            loader.insert("value.class", "memory = value(test);");
+
+            let mut cm = ClassManager::new();
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_loader(loader)
+                .execute_script("\"main.class\" = #include(); value(test) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["memory"]);
+
+            let mut cm = ClassManager::new();
+
+            ClassExecutor::new(&mut cm)
+                .with_loader(crate::script_bundle!["assets/class/planet.class"])
+                .execute_script("\"assets/class/planet.class\" = #include();")
+                .await
+                .unwrap();
+
+            assert_eq!(cm.get_target("view", "Planet").unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_namespace() {
+        block_on(async {
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert(
+                "planet.class",
+                r#"
+earth = view(Planet);
+{
+    $param: [$name],
+    $body: <
+        view(Planet) = $result();
+    >
+} = #proc(first);
+first({$name: Earth}) = cached(Planet);
+                "#,
+            );
+
+            let mut cm = ClassManager::new();
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_loader(loader)
+                .execute_script(
+                    r#"
+{$path: "planet.class", $as: planet} = #include();
+{$path: "planet.class", $as: planet} = #include();
+mars = view(Planet);
+[planet::view(Planet), planet::cached(Planet), planet::first({$name: Mars}), view(Planet)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["earth", "earth", "earth", "mars"]);
+            assert_eq!(cm.get_target("planet::view", "Planet").unwrap(), ["earth"]);
+
+            // Empty classes of the namespace read the global ones, writes stay in the namespace.
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert(
+                "theme.class",
+                r#"
+theme(main) = seen(before);
+light = theme(main);
+theme(main) = seen(after);
+                "#,
+            );
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_loader(loader)
+                .execute_script(
+                    r#"
+dark = theme(main);
+{$path: "theme.class", $as: ui} = #include();
+[ui::seen(before), ui::seen(after), theme(main)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["dark", "light", "dark"]);
+        });
+    }
+
+    #[test]
+    fn test_trace() {
+        block_on(async {
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert(
+                "lib.class",
+                "1 = a();\n<\n    \"boom\" = #throw();\n> = #call(lib);",
+            );
+
+            let mut cm = ClassManager::new();
+
+            let e = ClassExecutor::new(&mut cm)
+                .with_loader(loader)
+                .execute_script("\n\"lib.class\" = #include();")
+                .await
+                .unwrap_err();
+
+            let location_v = trace::trace_of(&e)
+                .iter()
+                .map(|location| location.to_string())
+                .collect::<Vec<String>>();
+
+            assert_eq!(
+                location_v,
+                ["at #call(lib):2", "at lib.class:2", "at <script>:2"]
+            );
+            assert_eq!(err::message(&e), "boom");
+        });
+    }
+
+    #[test]
+    fn test_policy() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            cm.append("view", "main", vec!["home".to_string()])
+                .await
+                .unwrap();
+            cm.append("secret", "main", vec!["42".to_string()])
+                .await
+                .unwrap();
+
+            let policy = policy::Policy::deny_all()
+                .allow_builtin("+")
+                .allow_read("view")
+                .allow_write("event");
+
+            let mut ce = ClassExecutor::new(&mut cm).with_policy(policy);
+
+            ce.execute_script(
+                r#"
+view(main) = event(click);
++({$left: 1, $right: 2}) = event(click);
+                "#,
+            )
+            .await
+            .unwrap();
+
+            for script in [
+                "secret(main) := $result();",
+                "1 = view(main);",
+                "[] = #dump(main);",
+                "\"lib.class\" = #include();",
+            ] {
+                let e = ce.execute_script(script).await.unwrap_err();
+
+                assert!(
+                    matches!(e.current_context(), err::Error::PermissionDenied),
+                    "{script}"
+                );
+            }
+
+            assert_eq!(cm.get_target("event", "click").unwrap(), ["home", "3"]);
+        });
+    }
+
+    #[test]
+    fn test_policy_builtin() {
+        block_on(async {
+            let owned = uuid::Uuid::new_v4().to_string();
+            let garbage = uuid::Uuid::new_v4().to_string();
+            let mut cm = ClassManager::new();
+
+            cm.append("ref", "main", vec![owned.clone()]).await.unwrap();
+            cm.append("secret", &owned, vec!["1".to_string()])
+                .await
+                .unwrap();
+            cm.append("secret", &garbage, vec!["2".to_string()])
+                .await
+                .unwrap();
+            cm.declare_object(&owned).await.unwrap();
+            cm.declare_object(&garbage).await.unwrap();
+
+            let policy = policy::Policy::deny_all()
+                .allow_builtin("#map")
+                .allow_builtin("#load")
+                .allow_builtin("#proc")
+                .allow_builtin("#delete")
+                .allow_builtin("#gc")
+                .allow_write("ref")
+                .allow_write("event");
+
+            let mut ce = ClassExecutor::new(&mut cm).with_policy(policy);
+
+            for script in [
+                "{$source: [1], $mapper: <$item() := $result();>} = #map({$class: secret, $source: main});",
+                "{$a: 1} = #load({$class: secret, $source: main});",
+                "{$param: [], $body: <>} = #proc(f);",
+                &format!("\"{owned}\" = #delete(cascade);"),
+                "[] = #gc();",
+            ] {
+                let e = ce.execute_script(script).await.unwrap_err();
+
+                assert!(
+                    matches!(e.current_context(), err::Error::PermissionDenied),
+                    "{script}"
+                );
+            }
+
+            // Allowed prefixes still go through.
+            ce.execute_script(
+                "{$source: [1], $mapper: <$item() := $result();>} = #map({$class: event, $source: main});",
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(cm.get_target("event", "main").unwrap(), ["1"]);
+            assert_eq!(cm.get_target("secret", &owned).unwrap(), ["1"]);
+            assert_eq!(cm.get_target("secret", &garbage).unwrap(), ["2"]);
+            assert!(cm.get_target("oncall", "f").is_none_or(|v| v.is_empty()));
+        });
+    }
+
+    #[test]
+    fn test_policy_namespace() {
+        block_on(async {
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert(
+                "ui.class",
+                r#"
+1 = $x();
+$x() = view(main);
+{$source: [2], $mapper: <$item() := $result();>} = #map({$class: view, $source: main});
+                "#,
+            );
+            loader.insert("proc.class", "{$param: [], $body: <>} = #proc(f);");
+
+            let mut cm = ClassManager::new();
+
+            let policy = policy::Policy::deny_all()
+                .allow_include(true)
+                .allow_builtin("#include")
+                .allow_builtin("#map")
+                .allow_write("ui::");
+
+            let mut ce = ClassExecutor::new(&mut cm)
+                .with_loader(loader)
+                .with_policy(policy);
+
+            ce.execute_script("{$path: \"ui.class\", $as: ui} = #include();")
+                .await
+                .unwrap();
+
+            // Writing in the namespace does not make every builtin callable.
+            let e = ce
+                .execute_script("{$path: \"proc.class\", $as: ui} = #include();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::PermissionDenied));
+            assert_eq!(cm.get_target("ui::view", "main").unwrap(), ["1", "2"]);
+            assert!(cm.get_target("ui::oncall", "f").is_none());
+        });
+    }
+
+    #[test]
+    fn test_dry_run() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            cm.append("view", "main", vec!["home".to_string()])
+                .await
+                .unwrap();
+            cm.append("onget", "#peek", vec!["1 = count(main);".to_string()])
+                .await
+                .unwrap();
+
+            for script in ["1 = view(main);", "#peek(main) := $result();"] {
+                let e = ReadOnlyClassExecutor::new(&cm)
+                    .execute_script(script)
+                    .await
+                    .unwrap_err();
+
+                assert!(
+                    matches!(e.current_context(), err::Error::PermissionDenied),
+                    "{script}"
+                );
+            }
+
+            // Denied as a whole, the object in the temp manager is kept too.
+            let temp_cm = Arc::new(Mutex::new(ClassManager::new()));
+            let mut ce = ReadOnlyClassExecutor::new_with_temp(&cm, temp_cm.clone());
+
+            ce.execute_script("{$name: a} = $x(o);").await.unwrap();
+
+            let e = ce.execute_script("$x(o) = #delete();").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::PermissionDenied));
+
+            let e = ce
+                .execute_script("[] := $x(o); [] = #gc();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::PermissionDenied));
+            assert_eq!(
+                temp_cm.lock().await.get_source("a", "$name").unwrap().len(),
+                1
+            );
+
+            let mut dry_run = dry_run::DryRun::new(&cm);
+
+            ClassExecutor::new(&mut dry_run)
+                .execute_script(
+                    r#"
+view(main) = $old();
+about := view(main);
+$old() = log(main);
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                dry_run.into_write_v(),
+                [
+                    dry_run::Write::Remove {
+                        class: "view".to_string(),
+                        source: "main".to_string(),
+                        target_v: vec!["home".to_string()],
+                    },
+                    dry_run::Write::Append {
+                        class: "view".to_string(),
+                        source: "main".to_string(),
+                        target_v: vec!["about".to_string()],
+                    },
+                    dry_run::Write::Append {
+                        class: "log".to_string(),
+                        source: "main".to_string(),
+                        target_v: vec!["home".to_string()],
+                    },
+                ]
+            );
+            assert_eq!(cm.get_target("view", "main").unwrap(), ["home"]);
+        });
+    }
+
+    #[test]
+    fn test_atomic() {
+        block_on(async {
+            let script = r#"
+view(main) = log(main);
+about := view(main);
+"boom" = #throw();
+            "#;
+
+            let mut cm = ClassManager::new();
+
+            cm.append("view", "main", vec!["home".to_string()])
+                .await
+                .unwrap();
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_atomic(true)
+                .execute_script(script)
+                .await;
+
+            assert!(rs.is_err());
+
+            assert_eq!(cm.get_target("view", "main").unwrap(), ["home"]);
+            assert!(cm.get_target("log", "main").unwrap().is_empty());
+
+            cm.begin().await.unwrap();
+            cm.append("log", "main", vec!["1".to_string()])
+                .await
+                .unwrap();
+            cm.begin().await.unwrap();
+            cm.append("log", "main", vec!["2".to_string()])
+                .await
+                .unwrap();
+            cm.rollback().await.unwrap();
+            cm.commit().await.unwrap();
+
+            assert_eq!(cm.get_target("log", "main").unwrap(), ["1"]);
+
+            let mut buffered = crate::buffered::Buffered::new(dry_run::DryRun::new(&cm));
+
+            let rs = ClassExecutor::new(&mut buffered)
+                .with_atomic(true)
+                .execute_script(script)
+                .await;
+
+            assert!(rs.is_err());
+
+            assert!(buffered.inner().write_v().is_empty());
+
+            let rs = ClassExecutor::new(&mut buffered)
+                .with_atomic(true)
+                .execute_script("about := view(main); view(main) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["about"]);
+            assert_eq!(buffered.into_inner().into_write_v().len(), 2);
+        });
+    }
+
+    /// A manager with nothing but the required methods, yielding to other tasks before a read
+    /// of `pause`. Sources are found in order of their names.
+    #[derive(Default)]
+    struct Minimal {
+        list_mp: std::collections::HashMap<(String, String), Vec<String>>,
+    }
+
+    impl AsClassManager for Minimal {
+        fn get<'a, 'a1, 'a2, 'f>(
+            &'a self,
+            class: &'a1 str,
+            source: &'a2 str,
+        ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+        where
+            'a: 'f,
+            'a1: 'f,
+            'a2: 'f,
+        {
+            Box::pin(async move {
+                if class == "pause" {
+                    tokio::task::yield_now().await;
+                }
+
+                let key = (class.to_string(), source.to_string());
+
+                Ok(self.list_mp.get(&key).cloned().unwrap_or_default())
+            })
+        }
+
+        fn remove<'a, 'a1, 'a2, 'f>(
+            &'a mut self,
+            class: &'a1 str,
+            source: &'a2 str,
+            target_v: Vec<String>,
+        ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+        where
+            'a: 'f,
+            'a1: 'f,
+            'a2: 'f,
+        {
+            Box::pin(async move {
+                let key = (class.to_string(), source.to_string());
+
+                if let Some(list) = self.list_mp.get_mut(&key) {
+                    list.retain(|target| !target_v.contains(target));
+                }
+
+                Ok(())
+            })
+        }
+
+        fn append<'a, 'a1, 'a2, 'f>(
+            &'a mut self,
+            class: &'a1 str,
+            source: &'a2 str,
+            target_v: Vec<String>,
+        ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+        where
+            'a: 'f,
+            'a1: 'f,
+            'a2: 'f,
+        {
+            Box::pin(async move {
+                let key = (class.to_string(), source.to_string());
+
+                self.list_mp.entry(key).or_default().extend(target_v);
+
+                Ok(())
+            })
+        }
+
+        fn get_source<'a, 'a1, 'a2, 'f>(
+            &'a self,
+            target: &'a1 str,
+            class: &'a2 str,
+        ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+        where
+            'a: 'f,
+            'a1: 'f,
+            'a2: 'f,
+        {
+            Box::pin(async move {
+                let mut source_v = self
+                    .list_mp
+                    .iter()
+                    .filter(|((c, _), list)| c == class && list.iter().any(|t| t == target))
+                    .map(|((_, source), _)| source.clone())
+                    .collect::<Vec<String>>();
+
+                source_v.sort();
+
+                Ok(source_v)
+            })
         }
     }
-}
 
-impl<'cm, AsCM: AsClassManager> def::AsClassManagerHolder for ReadOnlyClassExecutor<'cm, AsCM> {
-    type CM = AsCM;
+    #[test]
+    fn test_minimal_source() {
+        block_on(async {
+            let mut cm = Minimal::default();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+[home, about] = view(main);
+home = view(side);
+[a, b] = child(root);
+c = child(a);
+
+#source({$class: view, $target: home}) := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["main", "side"]);
+
+            let rs = ce
+                .execute_script("#ancestors({$class: child, $target: c}) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["a", "root"]);
+
+            let edge_v = cm
+                .query(Pattern {
+                    class: Some("child".to_string()),
+                    target: Some("b".to_string()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+
+            assert_eq!(
+                edge_v,
+                [("child".to_string(), "root".to_string(), "b".to_string())]
+            );
+        });
+    }
+
+    #[test]
+    fn test_atomic_journal() {
+        block_on(async {
+            let mut cm = Minimal::default();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            ce.execute_script("home = view(main); <$target() = note(main);> = onappend(#note);")
+                .await
+                .unwrap();
+
+            let rs = ce
+                .execute_script(
+                    r#"
+<
+    about := view(main);
+    <1 = count(main);> = #atomic();
+    x = #note();
+    "boom" = #throw();
+> = #atomic();
+                    "#,
+                )
+                .await;
+
+            assert!(rs.is_err());
+
+            let rs = ce
+                .execute_script(
+                    r#"
+<
+    about := view(main);
+    {$body: <<3 = count(main); "boom" = #throw();> = #atomic();>, $catch: <>} = #try();
+    y = #note();
+> = #atomic();
+
+[view(main), note(main), count(main)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["about", "y"]);
+
+            let rs = ClassExecutor::new(&mut cm)
+                .with_atomic(true)
+                .execute_script("home := view(main); z = #note(); \"boom\" = #throw();")
+                .await;
+
+            assert!(rs.is_err());
+            assert_eq!(cm.get("view", "main").await.unwrap(), ["about"]);
+            assert_eq!(cm.get("note", "main").await.unwrap(), ["y"]);
+        });
+    }
+
+    #[test]
+    fn test_atomic_shared_temp() {
+        block_on(async {
+            let temp_cm = Arc::new(Mutex::new(ClassManager::new()));
+            let mut cm_a = Minimal::default();
+            let mut cm_b = ClassManager::new();
+
+            let mut ce_a = ClassExecutor::new_with_temp(&mut cm_a, temp_cm.clone());
+            let mut ce_b = ClassExecutor::new_with_temp(&mut cm_b, temp_cm.clone());
+
+            // B runs while A waits on `pause`, in the middle of its block, and sees its write.
+            // Undoing the block on failure keeps the write of B to the same list.
+            let (rs_a, rs_b) = tokio::join!(
+                ce_a.execute_script(
+                    r#"<1 = $x(o); pause(p) = $w(o); "boom" = #throw();> = #atomic();"#
+                ),
+                ce_b.execute_script("$x(o) := $result(); 2 = $x(o);"),
+            );
+
+            assert!(rs_a.is_err());
+            assert_eq!(rs_b.unwrap(), ["1"]);
+
+            let rs = ce_a
+                .execute_script("<3 = $y(o);> = #atomic(); [$x(o), $y(o)] := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["2", "3"]);
+            assert_eq!(temp_cm.lock().await.get_target("$y", "o").unwrap(), ["3"]);
+        });
+    }
+
+    #[test]
+    fn test_buffered_delete() {
+        block_on(async {
+            let mut buffered = crate::buffered::Buffered::new(ClassManager::new());
+
+            let script = r#"
+{name: a, item: {name: x}} = child(root);
+{name: b} = $tmp(root);
+{name: c} = orphan(root);
+[] := orphan(root);
+            "#;
+
+            ClassExecutor::new(&mut buffered)
+                .execute_script(script)
+                .await
+                .unwrap();
+
+            let rs = ClassExecutor::new(&mut buffered)
+                .with_atomic(true)
+                .execute_script("child(root) = #delete(cascade); [] = #gc(); \"boom\" = #throw();")
+                .await;
+
+            assert!(rs.is_err());
+            assert_eq!(
+                buffered.inner().get_target("child", "root").unwrap().len(),
+                1
+            );
+            assert_eq!(buffered.inner().get_source("c", "name").unwrap().len(), 1);
+
+            ClassExecutor::new(&mut buffered)
+                .with_atomic(true)
+                .execute_script("child(root) = #delete(cascade); [] = #gc();")
+                .await
+                .unwrap();
+
+            let cm = buffered.into_inner();
+
+            assert!(cm.get_target("child", "root").unwrap().is_empty());
+            assert!(cm.get_source("x", "name").unwrap().is_empty());
+            assert!(cm.get_source("c", "name").unwrap().is_empty());
+
+            let mut cm = ClassManager::new();
+
+            ClassExecutor::new(&mut cm)
+                .execute_script(script)
+                .await
+                .unwrap();
+
+            let mut dry_run = dry_run::DryRun::new(&cm);
+
+            ClassExecutor::new(&mut dry_run)
+                .execute_script("child(root) = #delete(cascade);")
+                .await
+                .unwrap();
+
+            // The reference, the field of the object and the one of the object it owned.
+            assert_eq!(dry_run.into_write_v().len(), 4);
+            assert_eq!(cm.get_target("child", "root").unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn test_atomic_block() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+@{$x: 1, $y: 1} := camera(main);
+
+<
+    2 := $x(camera(main));
+    2 := $y(camera(main));
+    2 := zoom(main);
+    "boom" = #throw();
+> = #atomic();
+                    "#,
+                )
+                .await;
+
+            assert!(rs.is_err());
+
+            let rs = ce
+                .execute_script(
+                    r#"
+<
+    3 := $x(camera(main));
+    3 := zoom(main);
+> = #atomic();
+
+[$x(camera(main)), $y(camera(main)), zoom(main)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["3", "1", "3"]);
+        });
+    }
+
+    #[test]
+    fn test_compare_and_set() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+0 ?= count(main);
+5 ?= count(main);
+count(main) ~ +({$left: count(main), $right: 1}) := count(main);
+
+1 ?= $x();
+2 ?= $x();
+[] ~ 3 := $y();
+
+[count(main), $x(), $y()] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["1", "1", "3"]);
+
+            let e = ce
+                .execute_script("0 ~ 9 := count(main);")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::Conflict));
+            assert_eq!(cm.get_target("count", "main").unwrap(), ["1"]);
+        });
+    }
+
+    #[test]
+    fn test_index() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+[a, b, c] = child(view);
+z = child(view)[0];
+y = child(view)[4];
+[] -= child(view)[1];
+x := child(view)[0];
+{$class: child, $source: view, $from: 3, $to: 1} = #move();
+
+[p, q] = $list();
+r = $list()[1];
+
+[a, a] = dup(view);
+[] -= dup(view)[0];
+
+[child(view), $list(), dup(view)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["x", "y", "b", "c", "p", "r", "q", "a"]);
+
+            let e = ce
+                .execute_script("[] -= child(view)[9];")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+        });
+    }
+
+    #[test]
+    fn test_set_class() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            cm.mark_set("tag");
+
+            let rs = ClassExecutor::new(&mut cm)
+                .execute_script(
+                    r#"
+[a, b, a] = tag(x);
+[b, c] = tag(x);
+c = tag(x)[0];
+[a, a] = note(x);
+[tag(x), note(x)] := $result();
+                    "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["a", "b", "c", "a", "a"]);
+        });
+    }
+
+    #[test]
+    fn test_schema() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let schema = Schema::new()
+                .declare("$radius", ClassDef::one(ValueType::Number))
+                .declare("child", ClassDef::many(ValueType::Object))
+                .declare("name", ClassDef::one(ValueType::String).required());
+
+            let mut ce = ClassExecutor::new(&mut cm).with_schema(schema);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+1 = $radius();
+2 := $radius();
+{} = child(root);
+{} = child(root);
+Ann = name(root);
+$radius() = $result();
+                "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["2"]);
+
+            for (script, kind) in [
+                ("abc := $radius();", "TypeMismatch"),
+                ("3 = $radius();", "CardinalityViolated"),
+                ("root = child(root);", "TypeMismatch"),
+                ("Bob = name(root);", "CardinalityViolated"),
+                ("Ann -= name(root);", "MissingRequired"),
+                ("[] := name(root);", "MissingRequired"),
+            ] {
+                let e = ce.execute_script(script).await.unwrap_err();
+
+                assert_eq!(e.current_context().kind(), kind, "{script}");
+            }
+
+            assert_eq!(cm.get_target("name", "root").unwrap(), ["Ann"]);
+            assert_eq!(cm.get_target("child", "root").unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn test_delete() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            ce.execute_script(
+                r#"
+{name: a, item: {name: x}} = child(root);
+{name: b} = child(root);
+{name: p, item: {name: q}} = pair(root);
+#index({$source: child(root), $index: 0}) = #delete(cascade);
+pair(root) = #delete();
+                "#,
+            )
+            .await
+            .unwrap();
+
+            let e = ce.execute_script("a = #delete(all);").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+
+            let child_v = cm.get_target("child", "root").unwrap();
 
-    fn temp(&self) -> Arc<Mutex<ClassManager>> {
-        self.temp_cm.clone()
-    }
+            assert_eq!(child_v.len(), 1);
+            assert!(cm.get_target("pair", "root").unwrap().is_empty());
+            assert!(cm.get_source("x", "name").unwrap().is_empty());
+            assert_eq!(cm.get_source("q", "name").unwrap().len(), 1);
+            assert!(cm.dangling_v().is_empty());
 
-    fn global_ref(&self) -> &Self::CM {
-        self.global_cm
-    }
+            cm.remove("name", &child_v[0], vec!["b".to_string()])
+                .await
+                .unwrap();
 
-    fn global_mut(&mut self) -> Option<&mut Self::CM> {
-        None
+            assert_eq!(
+                cm.dangling_v(),
+                [("child".to_string(), "root".to_string(), child_v[0].clone())]
+            );
+        });
     }
 
-    fn path_mut(&mut self) -> &mut String {
-        &mut self.path
-    }
-}
+    #[test]
+    fn test_gc() {
+        block_on(async {
+            let mut cm = ClassManager::new();
 
-impl<'cm, CM: AsClassManager> ReadOnlyClassExecutor<'cm, CM> {
-    pub fn execute_script<'a, 'a1, 'f>(
-        &'a mut self,
-        script: &'a1 str,
-    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
-    where
-        'a: 'f,
-        'a1: 'f,
-    {
-        inner::execute_script(self, script)
-    }
-}
+            let mut ce = ClassExecutor::new(&mut cm).with_gc(gc::Gc::new(2));
+
+            ce.execute_script(
+                r#"
+{name: a} = child(root);
+{name: b} = $tmp();
+[] := $tmp();
+{name: c} = $x(main);
+{$n: e, name: f} = child(root);
+                "#,
+            )
+            .await
+            .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use crate::ClassManager;
+            assert_eq!(ce.global_cm.get_source("b", "name").unwrap().len(), 1);
 
-    use super::*;
+            let rs = ce.execute_script("{name: g} = $result();").await.unwrap();
 
-    #[test]
-    fn test_return() {
-        let _ =
-            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
-                .is_test(true)
-                .try_init();
+            // Collected after the second script, but the result was a root.
+            assert_eq!(ce.global_cm.get_source("g", "name").unwrap(), rs);
+            assert!(ce.global_cm.get_source("b", "name").unwrap().is_empty());
 
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
+            assert_eq!(
+                ce.execute_script("$n(child(root)) := $result(); [] = #gc();")
+                    .await
+                    .unwrap(),
+                ["e"]
+            );
 
-        rt.block_on(async {
-            log::debug!("start");
+            for name in ["a", "c", "f"] {
+                assert_eq!(cm.get_source(name, "name").unwrap().len(), 1, "{name}");
+            }
 
+            assert!(cm.get_source("g", "name").unwrap().is_empty());
+
+            // A source named by the host is kept, even if it looks like a minted id.
             let mut cm = ClassManager::new();
 
-            let rs = ClassExecutor::new(&mut cm)
-                .execute_script(
-                    "test = test(test);
-                    test(test) = $result();",
-                )
+            ClassExecutor::new(&mut cm)
+                .with_gc(gc::Gc::new(1))
+                .execute_script("alice := name(123e4567-e89b-12d3-a456-426614174000);")
                 .await
                 .unwrap();
 
-            assert_eq!(rs.len(), 1);
-            assert_eq!(rs[0], "test");
-        })
+            assert_eq!(
+                cm.get_target("name", "123e4567-e89b-12d3-a456-426614174000")
+                    .unwrap(),
+                ["alice"]
+            );
+        });
     }
 
     #[test]
-    fn test_add() {
-        let _ =
-            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
-                .is_test(true)
-                .try_init();
+    fn test_id_generator() {
+        block_on(async {
+            let script = r#"
+{name: a, item: {name: b}} = child(root);
+@{name: c} = child(root);
+            "#;
 
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
+            let mut cm = ClassManager::new();
 
-        rt.block_on(async {
-            log::debug!("start");
+            ClassExecutor::new(&mut cm)
+                .with_id_generator(id::SequentialId::new())
+                .execute_script(script)
+                .await
+                .unwrap();
+
+            // `@{` is minted when parsing, before the statements run.
+            assert_eq!(
+                cm.get_target("child", "root").unwrap(),
+                [
+                    "00000000-0000-0000-0000-000000000002",
+                    "00000000-0000-0000-0000-000000000001"
+                ]
+            );
+
+            let mut dump_v = vec![];
+
+            for seed in [7, 7, 8] {
+                let mut cm = ClassManager::new();
+
+                ClassExecutor::new(&mut cm)
+                    .with_id_generator(id::SeededId::new(seed))
+                    .execute_script(script)
+                    .await
+                    .unwrap();
+
+                let child_v = cm.get_target("child", "root").unwrap();
+
+                assert!(child_v.iter().all(|child| util::is_object_id(child)));
+
+                dump_v.push((child_v, cm.dump("root").dump()));
+            }
+
+            assert_eq!(dump_v[0], dump_v[1]);
+            assert_ne!(dump_v[0].0, dump_v[2].0);
 
+            // Executors on one store share the generator, or start past the ids it holds.
             let mut cm = ClassManager::new();
+            let id_generator = Arc::new(id::SequentialId::new());
 
-            let rs = ClassExecutor::new(&mut cm)
+            for _ in 0..2 {
+                ClassExecutor::new(&mut cm)
+                    .with_id_generator(id_generator.clone())
+                    .execute_script("{name: a} = item(root);")
+                    .await
+                    .unwrap();
+            }
+
+            ClassExecutor::new(&mut cm)
+                .with_id_generator(id::SequentialId::starting_at(3))
+                .execute_script("{name: a} = item(root);")
+                .await
+                .unwrap();
+
+            assert_eq!(
+                cm.get_target("item", "root").unwrap(),
+                [
+                    "00000000-0000-0000-0000-000000000001",
+                    "00000000-0000-0000-0000-000000000002",
+                    "00000000-0000-0000-0000-000000000003"
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_clone() {
+        block_on(async {
+            let mut cm = ClassManager::new();
+
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            let rs = ce
                 .execute_script(
-                    "1 = $left(test);
-                    1 = $right(test);
-                    +(test) = $result();",
+                    r#"
+{$name: a, $item: {$name: b}, $tag: [x, y]} = $props();
+#clone($props()) = $copy();
+#equal({$left: $props(), $right: $copy()}) = $result();
+                "#,
                 )
                 .await
                 .unwrap();
 
-            assert_eq!(rs.len(), 1);
-            assert_eq!(rs[0], "2");
-        })
+            assert_eq!(rs, ["1"]);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+c := $name($item($copy()));
+#diff({$left: $props(), $right: $copy()}) := $result();
+                "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["+$item[0].$name[0]: c", "-$item[0].$name[0]: b"]);
+
+            let rs = ce
+                .execute_script(
+                    r#"
+[$props(), $copy(), $item($props()), $item($copy())] := $result();
+#equal({$left: $props(), $right: $copy()}) = $result();
+                "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs.len(), 4);
+            assert_eq!(rs.iter().collect::<HashSet<_>>().len(), 4);
+
+            // Values are compared as they are, not as objects without edges.
+            let rs = ce
+                .execute_script(
+                    r#"
+#equal({$left: 1, $right: 2}) = $scalar();
+#equal({$left: [a], $right: [b]}) = $list();
+#equal({$left: [a, b], $right: [a, b]}) = $same();
+#equal({$left: {$v: 1}, $right: {$v: 2}}) = $leaf();
+[$scalar(), $list(), $same(), $leaf()] := $result();
+                "#,
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["1"]);
+        });
     }
 
     #[test]
-    fn test_obj() {
-        let _ =
-            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
-                .is_test(true)
-                .try_init();
+    fn test_query() {
+        block_on(async {
+            let mut cm = ClassManager::new();
 
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            ce.execute_script(
+                r#"
+home = view(main);
+dark = theme(main);
+about = view(side);
+main = layout(app);
+{$name: a} = $child(root);
+                "#,
+            )
+            .await
             .unwrap();
 
-        rt.block_on(async {
-            log::debug!("start");
+            for (script, expected) in [
+                (
+                    "#query({$source: main, $select: class}) := $result();",
+                    vec!["view", "theme"],
+                ),
+                (
+                    "#query({$class: view, $select: source}) := $result();",
+                    vec!["main", "side"],
+                ),
+                (
+                    "$class(#query({$target: home})) := $result();",
+                    vec!["view"],
+                ),
+                (
+                    "$name(#query({$class: $name, $select: source})) := $result();",
+                    vec!["a"],
+                ),
+            ] {
+                assert_eq!(
+                    ce.execute_script(script).await.unwrap(),
+                    expected,
+                    "{script}"
+                );
+            }
+
+            let e = ce
+                .execute_script("#query({$class: view, $select: edge}) = $result();")
+                .await
+                .unwrap_err();
 
+            assert!(matches!(e.current_context(), err::Error::RuntimeError));
+        });
+    }
+
+    #[test]
+    fn test_source() {
+        block_on(async {
             let mut cm = ClassManager::new();
 
             let mut ce = ClassExecutor::new(&mut cm);
@@ -664,68 +3687,195 @@ mod tests {
             let rs = ce
                 .execute_script(
                     r#"
-1 = $sum();
-2 = $pos();
-
-<
-    +(@{
-        $left: $sum(),
-        $right: $pos()
-    }) := $sum();
-
-    +(@{
-        $left: $pos(),
-        $right: 1
-    }) := $pos();
+home = view(main);
+home = view(side);
+about = view(info);
+#source({$class: view, $target: [home, about]}) = $result();
+                "#,
+                )
+                .await
+                .unwrap();
 
-    [
-        @{
-            $case: <#inner(@{$left: 101, $right: $pos()})>,
-            $then: <[] := $result();>
-        },
-        @{$case: <1>, $then: <1 := $result();>}
-    ] = #switch();
-> = #loop();
+            assert_eq!(rs, ["main", "side", "info"]);
 
-$sum() := $result();
-             "#,
+            let rs = ce
+                .execute_script(
+                    r#"
+{$tag: t} = $obj();
+#source({$class: $tag, $target: t}) := $result();
+                "#,
                 )
                 .await
                 .unwrap();
 
-            assert_eq!(rs.len(), 1);
-            assert_eq!(rs[0], "5050");
-        })
+            assert_eq!(rs, ce.get("$obj", "").await.unwrap());
+        });
     }
 
     #[test]
-    fn test_template() {
-        let _ =
-            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("debug"))
-                .is_test(true)
-                .try_init();
+    fn test_graph() {
+        block_on(async {
+            let mut cm = ClassManager::new();
 
-        let rt = tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
+            let mut ce = ClassExecutor::new(&mut cm);
+
+            ce.execute_script(
+                r#"
+[b, c] = child(a);
+d = child(b);
+e = child(c);
+f = child(d);
+a = child(f);
+                "#,
+            )
+            .await
             .unwrap();
 
-        rt.block_on(async {
-            let mut cm = ClassManager::new();
+            let rs = ce
+                .execute_script("#reachable({$class: child, $source: b}) = $result();")
+                .await
+                .unwrap();
 
-            let mut ce = ClassExecutor::new(&mut cm);
+            assert_eq!(rs, ["d", "f", "a", "b", "c", "e"]);
+
+            let rs = ce
+                .execute_script("#ancestors({$class: child, $target: e}) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["c", "a", "f", "d", "b"]);
+
+            let rs = ce
+                .execute_script("#path({$class: child, $from: b, $to: e}) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["b", "d", "f", "a", "c", "e"]);
+
+            let rs = ce
+                .execute_script("#path({$class: child, $from: e, $to: a}) := $result();")
+                .await
+                .unwrap();
+
+            assert!(rs.is_empty());
+
+            let rs = ce
+                .execute_script("#depth({$class: child, $target: e}) := $result();")
+                .await
+                .unwrap();
+
+            assert_eq!(rs, ["5"]);
 
             let rs = ce
                 .execute_script(
                     r#"
-value := $value();
-<${$value()}> := $result();
-            "#,
+[y] = $up(x);
+[z] = $up(y);
+#depth({$class: $up, $target: x}) := $result();
+                "#,
                 )
                 .await
                 .unwrap();
 
-            assert_eq!(rs[0], "value")
+            assert_eq!(rs, ["0"]);
+
+            assert!(ce
+                .execute_script("#path({$class: child, $from: a}) := $result();")
+                .await
+                .is_err());
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sandbox() {
+        let dir = std::env::temp_dir().join(format!("moon_class_{}", uuid::Uuid::new_v4()));
+        let root = dir.join("root");
+
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(dir.join("secret.class"), "1 = secret(test);").unwrap();
+        fs::write(root.join("lib/a.class"), "1 = a(test);").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.class"), root.join("link.class")).unwrap();
+
+        let mut cm = ClassManager::new();
+
+        block_on(async {
+            let mut ce = ClassExecutor::new(&mut cm).with_sandbox(&root);
+
+            ce.execute_script("\"lib/../lib/a.class\" = #include();")
+                .await
+                .unwrap();
+
+            for target in [
+                "../secret.class",
+                "lib/../../secret.class",
+                "link.class",
+                &dir.join("secret.class").to_string_lossy(),
+            ] {
+                let e = ce
+                    .execute_script(&format!("\"{target}\" = #include();"))
+                    .await
+                    .unwrap_err();
+
+                assert!(matches!(e.current_context(), err::Error::PermissionDenied));
+            }
+        });
+
+        assert!(cm.get_target("a", "test").is_some());
+        assert!(cm.get_target("secret", "test").is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox_hook() {
+        let dir = std::env::temp_dir().join(format!("moon_class_{}", uuid::Uuid::new_v4()));
+        let root = dir.join("root");
+
+        fs::create_dir_all(&root).unwrap();
+        fs::write(dir.join("secret.class"), "1 = secret(test);").unwrap();
+
+        let secret = dir.join("secret.class").to_string_lossy().to_string();
+        let mut cm = ClassManager::new();
+
+        block_on(async {
+            let mut ce = ClassExecutor::new(&mut cm).with_sandbox(&root);
+
+            ce.execute_script(&format!(
+                "<\"{secret}\" = #include();> = onappend(#escape);"
+            ))
+            .await
+            .unwrap();
+
+            let e = ce.execute_script("1 = #escape();").await.unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::PermissionDenied));
+
+            // The sandbox also holds a loader given after it.
+            let mut loader = loader::MemoryLoader::new();
+
+            loader.insert("lib.class", "1 = lib(test);");
+            loader.insert("/secret.class", "1 = secret(test);");
+
+            let mut ce = ClassExecutor::new(&mut cm)
+                .with_sandbox(&root)
+                .with_loader(loader);
+
+            ce.execute_script("\"lib.class\" = #include();")
+                .await
+                .unwrap();
+
+            let e = ce
+                .execute_script("\"/secret.class\" = #include();")
+                .await
+                .unwrap_err();
+
+            assert!(matches!(e.current_context(), err::Error::PermissionDenied));
         });
+
+        assert!(cm.get_target("lib", "test").is_some());
+        assert!(cm.get_target("secret", "test").is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,64 @@
+use std::{path::PathBuf, sync::Arc};
+
+use crate::schema::Schema;
+
+use super::{
+    id::{IdGenerator, RandomId},
+    limit::{CancelToken, Limit},
+    loader::{Confined, FileLoader, ScriptLoader},
+    policy::Policy,
+};
+
+/// Settings of an executor, handed down to the executors running its hooks.
+#[derive(Clone)]
+pub struct Config {
+    /// Where `#include` reads scripts, built from `custom_loader` and `sandbox`.
+    pub(crate) loader: Arc<dyn ScriptLoader>,
+    custom_loader: Option<Arc<dyn ScriptLoader>>,
+    sandbox: Option<PathBuf>,
+    /// Directories searched by `#include` after the one of the including file.
+    pub(crate) search_path_v: Vec<String>,
+    pub(crate) limit: Limit,
+    pub(crate) cancel: CancelToken,
+    pub(crate) policy: Arc<Policy>,
+    pub(crate) schema: Arc<Schema>,
+    pub(crate) id_generator: Arc<dyn IdGenerator>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            loader: Arc::new(FileLoader::new()),
+            custom_loader: None,
+            sandbox: None,
+            search_path_v: vec![],
+            limit: Limit::default(),
+            cancel: CancelToken::new(),
+            policy: Arc::new(Policy::default()),
+            schema: Arc::new(Schema::new()),
+            id_generator: Arc::new(RandomId),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn set_loader(&mut self, loader: Arc<dyn ScriptLoader>) {
+        self.custom_loader = Some(loader);
+        self.build_loader();
+    }
+
+    pub(crate) fn set_sandbox(&mut self, root: PathBuf) {
+        self.sandbox = Some(root);
+        self.build_loader();
+    }
+
+    /// The sandbox holds whichever of the two is set first.
+    fn build_loader(&mut self) {
+        self.loader = match (&self.custom_loader, &self.sandbox) {
+            (Some(loader), Some(_)) => Arc::new(Confined::new(loader.clone())),
+            (Some(loader), None) => loader.clone(),
+            (None, Some(root)) => Arc::new(FileLoader::sandboxed(root.clone())),
+            (None, None) => Arc::new(FileLoader::new()),
+        };
+    }
+}
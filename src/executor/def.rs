@@ -1,20 +1,105 @@
 use std::{pin::Pin, sync::Arc};
 
+use error_stack::ResultExt;
 use tokio::sync::Mutex;
 
-use crate::{def::{AsClassManager, Fu}, ClassManager};
+use crate::{
+    def::{AsClassManager, Fu},
+    err,
+    schema::Schema,
+    ClassManager, UndoLog,
+};
+
+use super::{
+    config::Config, id::IdGenerator, include::Include, journal::Journal, limit::Budget,
+    loader::ScriptLoader, policy::Policy, scope::Frame, trace::Location,
+};
+
+/// Control flow raised by `#return`, `#break` and `#continue`, unwinding until consumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Return(Vec<String>),
+    Break,
+    Continue,
+}
 
 pub trait AsClassManagerHolder {
     type CM: AsClassManager;
 
     fn temp(&self) -> Arc<Mutex<ClassManager>>;
 
+    /// Swapped for a private copy while `#atomic` runs.
+    fn temp_mut(&mut self) -> &mut Arc<Mutex<ClassManager>>;
+
     fn global_ref(&self) -> &Self::CM;
 
     fn global_mut(&mut self) -> Option<&mut Self::CM>;
 
+    /// The global manager to write `class(source)` to, denied if the holder is read-only.
+    fn writable_global(&mut self, class: &str, source: &str) -> err::Result<&mut Self::CM> {
+        match self.global_mut() {
+            Some(global) => Ok(global),
+            None => Err(err::Error::PermissionDenied).attach_printable_lazy(|| {
+                format!("{class}({source}): can not write with a read-only executor")
+            }),
+        }
+    }
+
     fn path_mut(&mut self) -> &mut String;
 
+    fn include_mut(&mut self) -> &mut Include;
+
+    /// Settings shared with the executors of hooks.
+    fn config(&self) -> &Config;
+
+    fn loader(&self) -> Arc<dyn ScriptLoader> {
+        self.config().loader.clone()
+    }
+
+    /// Namespace of the included file being executed, empty for none.
+    fn namespace(&self) -> &str;
+
+    fn namespace_mut(&mut self) -> &mut String;
+
+    /// Frames of nested scripts, innermost last.
+    fn frame_v(&self) -> &[Frame];
+
+    fn frame_v_mut(&mut self) -> &mut Vec<Frame>;
+
+    fn signal_mut(&mut self) -> &mut Option<Signal>;
+
+    /// Files, hooks and procedures being executed, innermost last.
+    fn trace_v_mut(&mut self) -> &mut Vec<Location>;
+
+    fn budget(&self) -> Arc<Budget>;
+
+    /// Undoes the writes to a global manager without transactions, `None` if the holder
+    /// can not write.
+    fn journal(&self) -> Option<Arc<Mutex<Journal>>> {
+        None
+    }
+
+    /// Changes to the temp manager of the open `#atomic` blocks, innermost last, `None` if
+    /// the holder has no such blocks.
+    fn temp_log(&self) -> Option<Arc<Mutex<Vec<UndoLog>>>> {
+        None
+    }
+
+    /// Capabilities of scripts, checked for every class they touch.
+    fn policy(&self) -> Arc<Policy> {
+        self.config().policy.clone()
+    }
+
+    /// Declared classes, validated for every write of a statement.
+    fn schema(&self) -> Arc<Schema> {
+        self.config().schema.clone()
+    }
+
+    /// Ids of the objects made by scripts.
+    fn id_generator(&self) -> Arc<dyn IdGenerator> {
+        self.config().id_generator.clone()
+    }
+
     fn dump<'a, 'a1, 'f>(
         &'a self,
         source: &'a1 str,
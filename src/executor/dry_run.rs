@@ -0,0 +1,240 @@
+use std::pin::Pin;
+
+use error_stack::ResultExt;
+
+use crate::{
+    def::{AsClassManager, Edge, Fu, Pattern, Step},
+    err,
+};
+
+/// A write a dry run would have made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Write {
+    Append {
+        class: String,
+        source: String,
+        target_v: Vec<String>,
+    },
+    Remove {
+        class: String,
+        source: String,
+        target_v: Vec<String>,
+    },
+}
+
+/// Reads through to a manager, recording writes instead of applying them.
+///
+/// ```
+/// use moon_class::{
+///     executor::{
+///         dry_run::{DryRun, Write},
+///         ClassExecutor,
+///     },
+///     ClassManager,
+/// };
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let cm = ClassManager::new();
+/// let mut dry_run = DryRun::new(&cm);
+///
+/// ClassExecutor::new(&mut dry_run)
+///     .execute_script("dark = theme(main);")
+///     .await
+///     .unwrap();
+///
+/// assert_eq!(
+///     dry_run.into_write_v(),
+///     [Write::Append {
+///         class: "theme".to_string(),
+///         source: "main".to_string(),
+///         target_v: vec!["dark".to_string()],
+///     }]
+/// );
+/// # });
+/// ```
+///
+/// Reads see the manager as it was before the run, not the recorded writes. Deleting and
+/// sweeping objects record the removal of their edges.
+pub struct DryRun<'cm, CM> {
+    global_cm: &'cm CM,
+    write_v: Vec<Write>,
+    savepoint_v: Vec<usize>,
+}
+
+impl<'cm, CM> DryRun<'cm, CM> {
+    pub fn new(global: &'cm CM) -> Self {
+        Self {
+            global_cm: global,
+            write_v: vec![],
+            savepoint_v: vec![],
+        }
+    }
+
+    pub fn write_v(&self) -> &[Write] {
+        &self.write_v
+    }
+
+    pub fn into_write_v(self) -> Vec<Write> {
+        self.write_v
+    }
+}
+
+impl<'cm, CM: AsClassManager> AsClassManager for DryRun<'cm, CM> {
+    fn get<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        source: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.global_cm.get(class, source)
+    }
+
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.global_cm.get_source(target, class)
+    }
+
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.global_cm.traverse(class, start, is_reverse)
+    }
+
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.global_cm.query(pattern)
+    }
+
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.global_cm.object_v()
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.global_cm.mark(root_v)
+    }
+
+    fn remove<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if !target_v.is_empty() {
+                self.write_v.push(Write::Remove {
+                    class: class.to_string(),
+                    source: source.to_string(),
+                    target_v,
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    fn append<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            if !target_v.is_empty() {
+                self.write_v.push(Write::Append {
+                    class: class.to_string(),
+                    source: source.to_string(),
+                    target_v,
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.savepoint_v.push(self.write_v.len());
+
+            Ok(())
+        })
+    }
+
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.savepoint_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("commit: no transaction")?;
+
+            Ok(())
+        })
+    }
+
+    /// Forgets the writes recorded since the transaction began.
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let savepoint = self
+                .savepoint_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("rollback: no transaction")?;
+
+            self.write_v.truncate(savepoint);
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// Schedule of the garbage collection run by an executor, shared by clones.
+///
+/// Named sources and locals are always roots, `root_v` adds the objects the host holds.
+#[derive(Debug, Clone)]
+pub struct Gc {
+    root_v: Vec<String>,
+    period: usize,
+    count: Arc<AtomicUsize>,
+}
+
+impl Gc {
+    /// Collects after every `period` scripts, `0` disables the schedule.
+    pub fn new(period: usize) -> Self {
+        Self {
+            root_v: vec![],
+            period,
+            count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_root(mut self, root: &str) -> Self {
+        self.root_v.push(root.to_string());
+        self
+    }
+
+    pub fn root_v(&self) -> &[String] {
+        &self.root_v
+    }
+
+    /// Counts one script, `true` if a collection is due.
+    pub fn tick(&self) -> bool {
+        if self.period == 0 {
+            return false;
+        }
+
+        (self.count.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(self.period)
+    }
+}
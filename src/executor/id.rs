@@ -0,0 +1,109 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::def::AsSendSyncOption;
+
+/// Source of the ids of objects made by object literals.
+pub trait IdGenerator: AsSendSyncOption {
+    fn next_id(&self) -> String;
+}
+
+/// Shares one generator between executors, so that they never mint the same id.
+impl<T: IdGenerator + ?Sized> IdGenerator for Arc<T> {
+    fn next_id(&self) -> String {
+        (**self).next_id()
+    }
+}
+
+/// Random v4 uuids, the default.
+#[derive(Debug, Default)]
+pub struct RandomId;
+
+impl IdGenerator for RandomId {
+    fn next_id(&self) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+/// `00000000-0000-0000-0000-000000000001`, `...-000000000002` and so on.
+///
+/// Every instance counts from its start: executors writing to one store share one through an
+/// `Arc`, and a store already holding its ids needs a start past them.
+#[derive(Debug)]
+pub struct SequentialId {
+    next: AtomicU64,
+}
+
+impl Default for SequentialId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SequentialId {
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// Counts from `first`.
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            next: AtomicU64::new(first),
+        }
+    }
+}
+
+impl IdGenerator for SequentialId {
+    fn next_id(&self) -> String {
+        let count = self.next.fetch_add(1, Ordering::Relaxed);
+
+        uuid::Uuid::from_u128(count as u128).to_string()
+    }
+}
+
+/// V4 uuids drawn from a splitmix64 sequence, the same seed gives the same ids.
+///
+/// Like [`SequentialId`], share one through an `Arc` between executors writing to one store,
+/// and never reuse a seed on a store already holding its ids: the seed is the namespace.
+#[derive(Debug)]
+pub struct SeededId {
+    state: AtomicU64,
+}
+
+impl SeededId {
+    const GAMMA: u64 = 0x9e3779b97f4a7c15;
+
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl IdGenerator for SeededId {
+    fn next_id(&self) -> String {
+        let state = self
+            .state
+            .fetch_add(Self::GAMMA.wrapping_mul(2), Ordering::Relaxed);
+
+        let high = Self::mix(state.wrapping_add(Self::GAMMA));
+        let low = Self::mix(state.wrapping_add(Self::GAMMA.wrapping_mul(2)));
+
+        let mut bytes = [0; 16];
+
+        bytes[..8].copy_from_slice(&high.to_be_bytes());
+        bytes[8..].copy_from_slice(&low.to_be_bytes());
+
+        uuid::Builder::from_random_bytes(bytes)
+            .into_uuid()
+            .to_string()
+    }
+}
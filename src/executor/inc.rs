@@ -7,9 +7,12 @@ use crate::{
     util::{str_of_value, value_of_str},
 };
 
-use super::string::{
-    find_angle_end, find_pat_ignoring_string, find_string_end, r_find_angle_start,
-    r_find_string_start,
+use super::{
+    id::IdGenerator,
+    string::{
+        find_angle_end, find_pat_ignoring_group, find_pat_ignoring_string, find_string_end,
+        r_find_angle_start, r_find_string_start,
+    },
 };
 
 #[derive(Debug)]
@@ -26,6 +29,7 @@ pub enum IncVal {
 
 impl IncVal {
     /// new('view(main)')(new('view(main)'))
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> err::Result<Self> {
         if s.is_empty() {
             return Ok(IncVal::Value(String::new()));
@@ -34,15 +38,7 @@ impl IncVal {
         if ((s.starts_with('{') || s.starts_with("@")) && s.ends_with('}'))
             || s.starts_with('[') && s.ends_with(']')
         {
-            if s.starts_with("@{") {
-                return Ok(IncVal::Object(format!(
-                    "@{}{}",
-                    uuid::Uuid::new_v4(),
-                    &s[1..]
-                )));
-            } else {
-                return Ok(IncVal::Object(s.to_string()));
-            }
+            return Ok(IncVal::Object(s.to_string()));
         }
 
         if s.starts_with('<') && s.ends_with('>') {
@@ -88,6 +84,20 @@ impl IncVal {
         Ok(IncVal::Value(value_of_str(s)))
     }
 
+    /// Gives `@{` objects an id, kept however many times the statement runs.
+    pub fn mint_ids(&mut self, id_generator: &dyn IdGenerator) {
+        match self {
+            IncVal::Object(s) if s.starts_with("@{") => {
+                *s = format!("@{}{}", id_generator.next_id(), &s[1..]);
+            }
+            IncVal::Addr((class, source)) => {
+                class.mint_ids(id_generator);
+                source.mint_ids(id_generator);
+            }
+            _ => {}
+        }
+    }
+
     pub fn as_value(&self) -> Option<&String> {
         match self {
             IncVal::Value(v) => Some(v),
@@ -119,6 +129,10 @@ pub enum Opt {
     Append,
     Set,
     Remove,
+    /// `?=`, sets if there is no target yet.
+    SetIfEmpty,
+    /// `expected ~ new :=`, sets if the targets are the expected ones.
+    CompareAndSet,
 }
 
 impl Display for Opt {
@@ -127,6 +141,8 @@ impl Display for Opt {
             Opt::Append => write!(f, "="),
             Opt::Set => write!(f, ":="),
             Opt::Remove => write!(f, "-="),
+            Opt::SetIfEmpty => write!(f, "?="),
+            Opt::CompareAndSet => write!(f, ":="),
         }
     }
 }
@@ -134,9 +150,14 @@ impl Display for Opt {
 #[derive(Debug)]
 pub struct Inc {
     target: IncVal,
+    /// Targets expected by [`Opt::CompareAndSet`].
+    expected: Option<IncVal>,
     operator: Opt,
     class: IncVal,
     source: IncVal,
+    /// Position in the targets, `c(s)[i]`.
+    index: Option<IncVal>,
+    line: usize,
 }
 
 impl Inc {
@@ -152,23 +173,62 @@ impl Inc {
         &self.target
     }
 
+    pub fn expected(&self) -> Option<&IncVal> {
+        self.expected.as_ref()
+    }
+
+    pub fn index(&self) -> Option<&IncVal> {
+        self.index.as_ref()
+    }
+
     pub fn operator(&self) -> &Opt {
         &self.operator
     }
 
+    pub fn mint_ids(&mut self, id_generator: &dyn IdGenerator) {
+        for inc_val in [&mut self.class, &mut self.source, &mut self.target]
+            .into_iter()
+            .chain(self.expected.as_mut())
+            .chain(self.index.as_mut())
+        {
+            inc_val.mint_ids(id_generator);
+        }
+    }
+
+    /// Line of the statement in its script, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
     /// new('view(main)'), ?
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> err::Result<Self> {
         let mut pos = 0;
 
         let mut target_op = None;
+        let mut expected_op = None;
         let mut operator_op = None;
 
         while pos < s.len() {
             if s[pos..].starts_with(":=") {
                 log::debug!("from_str: {s}");
 
+                let target = s[..pos].trim();
+
+                if let Some(tilde) = find_pat_ignoring_group("~", target)? {
+                    expected_op = Some(IncVal::from_str(target[..tilde].trim())?);
+                    target_op = Some(IncVal::from_str(target[tilde + 1..].trim())?);
+                    operator_op = Some(Opt::CompareAndSet);
+                } else {
+                    target_op = Some(IncVal::from_str(target)?);
+                    operator_op = Some(Opt::Set);
+                }
+
+                pos += 1;
+                break;
+            } else if s[pos..].starts_with("?=") {
                 target_op = Some(IncVal::from_str(s[..pos].trim())?);
-                operator_op = Some(Opt::Set);
+                operator_op = Some(Opt::SetIfEmpty);
 
                 pos += 1;
                 break;
@@ -208,7 +268,9 @@ impl Inc {
             pos += 1;
         }
 
-        let (class, source) = match IncVal::from_str(s[pos + 1..].trim())? {
+        let (addr, index) = split_index(s[pos + 1..].trim())?;
+
+        let (class, source) = match IncVal::from_str(addr)? {
             IncVal::Addr((class, source)) => (*class, *source),
             _ => {
                 return Err(err::Error::SyntaxError).attach_printable_lazy(|| {
@@ -217,31 +279,94 @@ impl Inc {
             }
         };
 
+        let operator = operator_op.unwrap();
+
+        let index = match index {
+            Some(index) => {
+                if matches!(operator, Opt::SetIfEmpty | Opt::CompareAndSet) {
+                    return Err(err::Error::SyntaxError).attach_printable_lazy(|| {
+                        format!("{s}: '{operator}' can not take an index")
+                    });
+                }
+
+                Some(IncVal::from_str(index)?)
+            }
+            None => None,
+        };
+
         Ok(Self {
             target: target_op.unwrap(),
-            operator: operator_op.unwrap(),
+            expected: expected_op,
+            operator,
             class,
             source,
+            index,
+            line: 1,
         })
     }
 }
 
 impl Display for Inc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(expected) = &self.expected {
+            write!(f, "{expected} ~ ")?;
+        }
+
         write!(
             f,
             "{} {} {}({})",
             self.target, self.operator, self.class, self.source
-        )
+        )?;
+
+        if let Some(index) = &self.index {
+            write!(f, "[{index}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `c(s)[i]` -> (`c(s)`, `Some("i")`).
+fn split_index(s: &str) -> err::Result<(&str, Option<&str>)> {
+    if !s.ends_with(']') {
+        return Ok((s, None));
+    }
+
+    let end = s.len() - 1;
+
+    let start = match r_find_angle_start(&s[..end], "[", "]")? {
+        Some(offset) => end - 1 - offset,
+        None => return Ok((s, None)),
+    };
+
+    let addr = s[..start].trim_end();
+
+    if addr.ends_with(')') {
+        Ok((addr, Some(s[start + 1..end].trim())))
+    } else {
+        Ok((s, None))
     }
 }
 
 pub fn inc_v_from_str(mut s: &str) -> err::Result<Vec<Inc>> {
     let mut inc_v = vec![];
+    let mut line = 1;
 
     while let Some(pos) = find_pat_ignoring_string(";", s)? {
-        inc_v.push(Inc::from_str(s[..pos].trim())?);
+        let statement = &s[..pos];
+        let body = statement.trim_start();
+        let start = line
+            + statement[..statement.len() - body.len()]
+                .matches('\n')
+                .count();
 
+        let mut inc =
+            Inc::from_str(body.trim_end()).attach_printable_lazy(|| format!("at line {start}"))?;
+
+        inc.line = start;
+        inc_v.push(inc);
+
+        line += statement.matches('\n').count();
         s = &s[pos + 1..];
     }
 
@@ -297,4 +422,53 @@ mod tests {
             "\"test\" = \"new\"(\"view(main)\");\n[{<test;=(>}] = \"new\"(\"view(main)\");\n"
         )
     }
+
+    #[test]
+    fn test_compare_and_set() {
+        let inc = Inc::from_str("[0] ~ 1 := count(main)").unwrap();
+
+        assert!(matches!(inc.operator(), Opt::CompareAndSet));
+        assert_eq!(inc.to_string(), "[0] ~ \"1\" := \"count\"(\"main\")");
+
+        let inc = Inc::from_str("1 ?= count(main)").unwrap();
+
+        assert!(matches!(inc.operator(), Opt::SetIfEmpty));
+
+        // `~` inside objects, lists and sources is part of the value.
+        let inc = Inc::from_str("{$a: x~y, $b: [1~2]} ~ [p~q] := count(main)").unwrap();
+
+        assert_eq!(
+            inc.to_string(),
+            "{$a: x~y, $b: [1~2]} ~ [p~q] := \"count\"(\"main\")"
+        );
+
+        let inc = Inc::from_str("a(b~c) ~ 1 := count(main)").unwrap();
+
+        assert_eq!(inc.expected().unwrap().to_string(), "\"a\"(\"b~c\")");
+    }
+
+    #[test]
+    fn test_index() {
+        let inc = Inc::from_str("a = $child(view)[$i()]").unwrap();
+
+        assert_eq!(
+            inc.to_string(),
+            "\"a\" = \"$child\"(\"view\")[\"$i\"(\"\")]"
+        );
+
+        let inc = Inc::from_str("[a] = list([1])").unwrap();
+
+        assert!(inc.index().is_none());
+        assert!(Inc::from_str("a ?= list(x)[0]").is_err());
+    }
+
+    #[test]
+    fn test_line() {
+        let inc_v = inc_v_from_str("a = b();\n\nc = d(\n  e\n);\nf = g();").unwrap();
+
+        assert_eq!(
+            inc_v.iter().map(|inc| inc.line()).collect::<Vec<usize>>(),
+            [1, 3, 6]
+        );
+    }
 }
@@ -0,0 +1,64 @@
+use std::{collections::HashSet, path::Path};
+
+use error_stack::ResultExt;
+
+use crate::err;
+
+use super::loader::ScriptLoader;
+
+/// Finds `target` in `dir`, then in every search path.
+pub fn resolve(
+    loader: &dyn ScriptLoader,
+    search_path_v: &[String],
+    dir: &str,
+    target: &str,
+) -> err::Result<String> {
+    let candidate_v = std::iter::once(dir)
+        .chain(search_path_v.iter().map(|path| path.as_str()))
+        .map(|dir| Path::new(dir).join(target).to_string_lossy().to_string());
+
+    for candidate in candidate_v {
+        if let Some(key) = loader.resolve(&candidate)? {
+            return Ok(key);
+        }
+    }
+
+    Err(err::Error::NotFound).attach_printable_lazy(|| {
+        format!("#include: {target} not found in {dir} or {search_path_v:?}")
+    })
+}
+
+/// Book-keeping of `#include`: scripts already included and the current chain.
+#[derive(Debug, Default)]
+pub struct Include {
+    done_set: HashSet<String>,
+    chain_v: Vec<String>,
+}
+
+impl Include {
+    /// Returns `false` if the script was included before, fails if it is being included.
+    pub fn enter(&mut self, key: &str) -> err::Result<bool> {
+        if self.chain_v.iter().any(|k| k == key) {
+            return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+                format!("#include: cycle {} -> {key}", self.chain_v.join(" -> "))
+            });
+        }
+
+        if self.done_set.contains(key) {
+            return Ok(false);
+        }
+
+        self.chain_v.push(key.to_string());
+
+        Ok(true)
+    }
+
+    /// Leaves the innermost script, remembering it only if it ran through.
+    pub fn leave(&mut self, is_done: bool) {
+        if let Some(key) = self.chain_v.pop() {
+            if is_done {
+                self.done_set.insert(key);
+            }
+        }
+    }
+}
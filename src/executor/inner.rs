@@ -1,11 +1,14 @@
-use std::pin::Pin;
+use std::{collections::HashSet, pin::Pin};
 
 use crate::{
-    def::{AsClassManager, AsSetable, Fu},
-    err,
+    def::{AsClassManager, AsSetable, Fu, Pattern},
+    err, util, UndoLog,
 };
 
-use super::*;
+use super::{
+    def::{AsClassManagerHolder, Signal},
+    *,
+};
 
 pub fn unwrap_value<'a, 'a1, 'f, CM>(
     ce: &'a mut CM,
@@ -14,7 +17,7 @@ pub fn unwrap_value<'a, 'a1, 'f, CM>(
 where
     'a: 'f,
     'a1: 'f,
-    CM: AsClassManager,
+    CM: AsClassManager + AsClassManagerHolder,
 {
     Box::pin(async move {
         match inc_val {
@@ -22,13 +25,32 @@ where
             inc::IncVal::Script(v) => value_extractor::script(ce, v).await,
             inc::IncVal::Value(v) => Ok(vec![v.clone()]),
             inc::IncVal::Addr((class, source)) => {
+                // f({$a: 1}) may call a procedure.
+                let is_call =
+                    matches!(source.as_ref(), inc::IncVal::Object(s) if s.starts_with('{'));
+
                 let class_v = unwrap_value(ce, class).await?;
                 let source_v = unwrap_value(ce, source).await?;
                 let mut rs = vec![];
 
                 for class in &class_v {
+                    ce.policy().check_read(class)?;
+
                     for source in &source_v {
-                        rs.extend(ce.get(class, &source).await?);
+                        if is_call && is_procedure_name(class) {
+                            let (oncall, name) = procedure_key(class);
+                            let body_v = ce.get(&oncall, name).await?;
+
+                            if !body_v.is_empty() {
+                                rs.extend(
+                                    call_procedure(ce, &oncall, name, source, &body_v).await?,
+                                );
+
+                                continue;
+                            }
+                        }
+
+                        rs.extend(ce.get(class, source).await?);
                     }
                 }
 
@@ -38,6 +60,117 @@ where
     })
 }
 
+fn is_procedure_name(class: &str) -> bool {
+    !class.starts_with(['$', '#', '^']) && !["+", "-", "*", "/", "%"].contains(&class)
+}
+
+/// Denies data in `class` if it names a procedure, which a read with an object source would
+/// run instead of the data.
+pub fn check_data<'a, 'a1, 'f, CM>(
+    ce: &'a CM,
+    class: &'a1 str,
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    CM: AsClassManager,
+{
+    Box::pin(async move {
+        if !is_procedure_name(class) {
+            return Ok(());
+        }
+
+        let (oncall, name) = procedure_key(class);
+
+        if ce.get(&oncall, name).await?.is_empty() {
+            Ok(())
+        } else {
+            Err(err::Error::RuntimeError)
+                .attach_printable_lazy(|| format!("{class} is a procedure, not a data class"))
+        }
+    })
+}
+
+/// `planet::f` -> (`planet::oncall`, `f`), procedures live in the namespace defining them.
+fn procedure_key(name: &str) -> (String, &str) {
+    match name.rsplit_once("::") {
+        Some((namespace, name)) => (format!("{namespace}::oncall"), name),
+        None => ("oncall".to_string(), name),
+    }
+}
+
+/// Runs the procedure `name` in a new frame with its parameters bound from `arg`.
+///
+/// The return value is the `$result` of that frame, the caller's `$result` stays untouched.
+pub fn call_procedure<'a, 'a1, 'a2, 'a3, 'a4, 'f, CM>(
+    ce: &'a mut CM,
+    oncall: &'a1 str,
+    name: &'a2 str,
+    arg: &'a3 str,
+    body_v: &'a4 [String],
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    'a3: 'f,
+    'a4: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        log::debug!("call_procedure: {oncall}({name}) with {arg}");
+
+        let param_v = ce.get(&format!("{oncall}:param"), name).await?;
+        let mut frame = scope::Frame::default();
+
+        for param in &param_v {
+            frame.append(param, ce.get(param, arg).await?);
+        }
+
+        // The body sees the classes of the namespace defining it.
+        let outer_namespace = match oncall.rsplit_once("::") {
+            Some((namespace, _)) => {
+                Some(std::mem::replace(ce.namespace_mut(), namespace.to_string()))
+            }
+            None => None,
+        };
+
+        ce.frame_v_mut().push(frame);
+
+        let file = match oncall.rsplit_once("::") {
+            Some((namespace, _)) => format!("{namespace}::{name}"),
+            None => name.to_string(),
+        };
+        let rs = execute_file(ce, &file, &rs_2_str(body_v)).await;
+
+        let frame = ce.frame_v_mut().pop().unwrap();
+
+        if let Some(namespace) = outer_namespace {
+            *ce.namespace_mut() = namespace;
+        }
+
+        rs?;
+
+        match take_return(ce)? {
+            Some(rs) => Ok(rs),
+            None => Ok(frame.get("$result").cloned().unwrap_or_default()),
+        }
+    })
+}
+
+/// Consumes the signal reaching the end of a script, giving the value of `#return` if any.
+pub fn take_return<CM>(ce: &mut CM) -> err::Result<Option<Vec<String>>>
+where
+    CM: AsClassManagerHolder,
+{
+    match ce.signal_mut().take() {
+        Some(Signal::Return(rs)) => Ok(Some(rs)),
+        Some(signal) => Err(err::Error::RuntimeError)
+            .attach_printable_lazy(|| format!("{signal:?} outside of #loop or #map")),
+        None => Ok(None),
+    }
+}
+
 pub fn execute<'a, 'a1, 'f, CM>(
     ce: &'a mut CM,
     inc_v: &'a1 [inc::Inc],
@@ -45,45 +178,445 @@ pub fn execute<'a, 'a1, 'f, CM>(
 where
     'a: 'f,
     'a1: 'f,
-    CM: AsClassManager,
+    CM: AsClassManager + AsClassManagerHolder,
 {
     Box::pin(async move {
+        // Nested scripts continue the lines of the statement holding them.
+        let base = ce
+            .trace_v_mut()
+            .last()
+            .map_or(0, |location| location.line - 1);
+
         for inc in inc_v {
-            log::debug!("execute: {inc}");
+            let location = match ce.trace_v_mut().last_mut() {
+                Some(location) => {
+                    location.line = base + inc.line();
+                    location.clone()
+                }
+                None => trace::Location::new("<script>"),
+            };
 
-            let class_v = unwrap_value(ce, inc.class()).await?;
-            let source_v = unwrap_value(ce, inc.source()).await?;
-            let target_v = unwrap_value(ce, inc.target()).await?;
+            execute_inc(ce, inc, &location)
+                .await
+                .attach_printable_lazy(|| location.clone())?;
 
-            match inc.operator() {
-                inc::Opt::Append => {
-                    for class in &class_v {
-                        for source in &source_v {
-                            ce.append(class, source, target_v.clone()).await?;
-                        }
+            if ce.signal_mut().is_some() {
+                break;
+            }
+        }
+
+        ce.get("$result", "").await
+    })
+}
+
+fn execute_inc<'a, 'a1, 'a2, 'f, CM>(
+    ce: &'a mut CM,
+    inc: &'a1 inc::Inc,
+    location: &'a2 trace::Location,
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        ce.budget().step()?;
+
+        log::debug!("execute: {location}: {inc}");
+
+        let class_v = unwrap_value(ce, inc.class()).await?;
+        let source_v = unwrap_value(ce, inc.source()).await?;
+        let target_v = unwrap_value(ce, inc.target()).await?;
+
+        for class in &class_v {
+            check_write(ce, class)?;
+            check_data(ce, class).await?;
+        }
+
+        let index = match inc.index() {
+            Some(index) => match unwrap_value(ce, index).await?.first() {
+                Some(index) => Some(parse_number::<usize>(index)?),
+                None => {
+                    return Err(err::Error::RuntimeError)
+                        .attach_printable_lazy(|| format!("{inc}: the index is empty"));
+                }
+            },
+            None => None,
+        };
+
+        for class in &class_v {
+            for source in &source_v {
+                check_schema(ce, inc.operator(), index, class, source, &target_v).await?;
+            }
+        }
+
+        if let Some(index) = index {
+            return execute_at(ce, inc.operator(), &class_v, &source_v, index, target_v).await;
+        }
+
+        match inc.operator() {
+            inc::Opt::Append => {
+                for class in &class_v {
+                    for source in &source_v {
+                        ce.append(class, source, target_v.clone()).await?;
                     }
                 }
-                inc::Opt::Remove => {
-                    for class in &class_v {
-                        for source in &source_v {
-                            ce.remove(class, source, target_v.clone()).await?;
-                        }
+            }
+            inc::Opt::Remove => {
+                for class in &class_v {
+                    for source in &source_v {
+                        ce.remove(class, source, target_v.clone()).await?;
+                    }
+                }
+            }
+            inc::Opt::Set => {
+                for class in &class_v {
+                    for source in &source_v {
+                        ce.set(class, source, target_v.clone()).await?;
+                    }
+                }
+            }
+            inc::Opt::SetIfEmpty => {
+                for class in &class_v {
+                    for source in &source_v {
+                        ce.compare_and_set(class, source, vec![], target_v.clone())
+                            .await?;
                     }
                 }
-                inc::Opt::Set => {
-                    for class in &class_v {
-                        for source in &source_v {
-                            ce.set(class, source, target_v.clone()).await?;
+            }
+            inc::Opt::CompareAndSet => {
+                let expected_v = match inc.expected() {
+                    Some(expected) => unwrap_value(ce, expected).await?,
+                    None => vec![],
+                };
+
+                for class in &class_v {
+                    for source in &source_v {
+                        if !ce
+                            .compare_and_set(class, source, expected_v.clone(), target_v.clone())
+                            .await?
+                        {
+                            return Err(err::Error::Conflict).attach_printable_lazy(|| {
+                                format!("{class}({source}) is not {expected_v:?}")
+                            });
                         }
                     }
                 }
             }
         }
 
-        ce.get("$result", "").await
+        Ok(())
+    })
+}
+
+/// `(class, source)` of a list.
+type Key = (String, String);
+
+/// Checks that the policy of `ce` allows a write to `class`, a call if it is a builtin.
+pub fn check_write<CM>(ce: &CM, class: &str) -> err::Result<()>
+where
+    CM: AsClassManagerHolder,
+{
+    let policy = ce.policy();
+
+    if policy::is_builtin(class) {
+        policy.check_builtin(class)
+    } else {
+        policy.check_write(&qualify(ce.namespace(), class))
+    }
+}
+
+/// Global lists `(class, source)` deleting `source` changes, of all the objects below it if
+/// `is_cascade`.
+pub fn delete_key_v<'a, 'a1, 'f, CM>(
+    ce: &'a CM,
+    source: &'a1 str,
+    is_cascade: bool,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Key>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        let mut key_v: Vec<Key> = vec![];
+        let mut done_set = HashSet::new();
+        let mut pending_v = vec![source.to_string()];
+
+        while let Some(source) = pending_v.pop() {
+            if !done_set.insert(source.clone()) {
+                continue;
+            }
+
+            let mut edge_v = ce
+                .global_ref()
+                .query(Pattern {
+                    source: Some(source.clone()),
+                    ..Default::default()
+                })
+                .await?;
+
+            edge_v.extend(
+                ce.global_ref()
+                    .query(Pattern {
+                        target: Some(source.clone()),
+                        ..Default::default()
+                    })
+                    .await?,
+            );
+
+            for (class, edge_source, target) in edge_v {
+                if is_cascade && edge_source == source && util::is_object_id(&target) {
+                    pending_v.push(target);
+                }
+
+                let key = (class, edge_source);
+
+                if !key_v.contains(&key) {
+                    key_v.push(key);
+                }
+            }
+        }
+
+        Ok(key_v)
+    })
+}
+
+/// Keeps `log` of the temp manager in the innermost `#atomic` block of `ce`, if any.
+pub fn log_temp<'a, 'f, CM>(ce: &'a CM, log: UndoLog) -> Pin<Box<dyn Fu<Output = ()> + 'f>>
+where
+    'a: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        if let Some(temp_log) = ce.temp_log() {
+            if let Some(level) = temp_log.lock().await.last_mut() {
+                level.extend(log);
+            }
+        }
+    })
+}
+
+/// Declares the minted `source` an object in the temp manager of `ce`, and in the global one
+/// if `is_global`, where its fields are.
+pub fn declare_object<'a, 'a1, 'f, CM>(
+    ce: &'a mut CM,
+    source: &'a1 str,
+    is_global: bool,
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        ce.temp().lock().await.declare_object(source).await?;
+
+        if is_global {
+            ce.writable_global("#declare", source)?
+                .declare_object(source)
+                .await?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Global lists `(class, source)` sweeping the objects not in `live_v` changes.
+pub fn sweep_key_v<'a, 'a1, 'f, CM>(
+    ce: &'a CM,
+    live_v: &'a1 [String],
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Key>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        let live_set = live_v.iter().collect::<HashSet<&String>>();
+        let object_set = ce
+            .global_ref()
+            .object_v()
+            .await?
+            .into_iter()
+            .collect::<HashSet<String>>();
+        let mut key_v: Vec<Key> = vec![];
+
+        for (class, source, _) in ce.global_ref().query(Pattern::default()).await? {
+            let key = (class, source);
+
+            if object_set.contains(&key.1) && !live_set.contains(&key.1) && !key_v.contains(&key) {
+                key_v.push(key);
+            }
+        }
+
+        Ok(key_v)
+    })
+}
+
+/// `true` if the writes of `ce` to the global manager are in its journal.
+pub fn is_journaled<'a, 'f, CM>(ce: &'a CM) -> Pin<Box<dyn Fu<Output = bool> + 'f>>
+where
+    'a: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        match ce.journal() {
+            Some(journal) => journal.lock().await.is_active(),
+            None => false,
+        }
+    })
+}
+
+/// Records `class(source)` of the global manager before a write, if the journal of `ce`
+/// is undoing them.
+pub fn record<'a, 'a1, 'a2, 'f, CM>(
+    ce: &'a CM,
+    class: &'a1 str,
+    source: &'a2 str,
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        let Some(journal) = ce.journal() else {
+            return Ok(());
+        };
+
+        let mut journal = journal.lock().await;
+
+        if journal.needs(class, source) {
+            let target_v = ce.global_ref().get(class, source).await?;
+
+            journal.record(class, source, target_v);
+        }
+
+        Ok(())
+    })
+}
+
+/// Class of `class` in the schema: locals without their depth, globals qualified.
+fn schema_key(namespace: &str, class: &str) -> String {
+    match scope::split_depth(class) {
+        Some((_, bare)) => bare.to_string(),
+        None if class.starts_with('#') => class.to_string(),
+        None => qualify(namespace, class),
+    }
+}
+
+/// Validates the write of `target_v` to `class(source)` against the schema of `ce`.
+fn check_schema<'a, 'a1, 'a2, 'a3, 'a4, 'f, CM>(
+    ce: &'a mut CM,
+    operator: &'a1 inc::Opt,
+    index: Option<usize>,
+    class: &'a2 str,
+    source: &'a3 str,
+    target_v: &'a4 [String],
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    'a3: 'f,
+    'a4: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        let schema = ce.schema();
+        let key = schema_key(ce.namespace(), class);
+
+        if schema.get(&key).is_none() {
+            return Ok(());
+        }
+
+        let current_v = ce.get(class, source).await?;
+        let count = current_v.len();
+
+        match (operator, index) {
+            (inc::Opt::Append, _) => schema.check_append(&key, source, count, target_v),
+            (inc::Opt::Remove, None) => {
+                let remain = current_v
+                    .iter()
+                    .filter(|target| !target_v.contains(target))
+                    .count();
+
+                schema.check_remain(&key, source, remain)
+            }
+            (inc::Opt::Remove, Some(_)) => {
+                schema.check_remain(&key, source, count.saturating_sub(1))
+            }
+            (inc::Opt::Set, Some(_)) => {
+                schema.check_append(&key, source, count.saturating_sub(1), target_v)?;
+                schema.check_remain(&key, source, count.saturating_sub(1) + target_v.len())
+            }
+            (inc::Opt::Set | inc::Opt::SetIfEmpty | inc::Opt::CompareAndSet, _) => {
+                schema.check_append(&key, source, 0, target_v)?;
+                schema.check_remain(&key, source, target_v.len())
+            }
+        }
+    })
+}
+
+/// `c(s)[i]`: `=` inserts before `i`, `-=` removes the target at `i`, `:=` replaces it.
+fn execute_at<'a, 'a1, 'a2, 'a3, 'f, CM>(
+    ce: &'a mut CM,
+    operator: &'a1 inc::Opt,
+    class_v: &'a2 [String],
+    source_v: &'a3 [String],
+    index: usize,
+    target_v: Vec<String>,
+) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    'a3: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        for class in class_v {
+            for source in source_v {
+                match operator {
+                    inc::Opt::Append => ce.insert(class, source, index, target_v.clone()).await?,
+                    inc::Opt::Remove => {
+                        ce.remove_at(class, source, index).await?;
+                    }
+                    inc::Opt::Set => {
+                        ce.remove_at(class, source, index).await?;
+                        ce.insert(class, source, index, target_v.clone()).await?;
+                    }
+                    inc::Opt::SetIfEmpty | inc::Opt::CompareAndSet => {
+                        return Err(err::Error::SyntaxError).attach_printable_lazy(|| {
+                            format!("'{operator}' can not take an index")
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
     })
 }
 
+/// Parses `script`, giving its `@{` objects ids from the generator of `ce`.
+pub fn parse<CM>(ce: &CM, script: &str) -> err::Result<Vec<inc::Inc>>
+where
+    CM: AsClassManagerHolder,
+{
+    let mut inc_v = inc::inc_v_from_str(script)?;
+    let id_generator = ce.id_generator();
+
+    for inc in &mut inc_v {
+        inc.mint_ids(id_generator.as_ref());
+    }
+
+    Ok(inc_v)
+}
+
 pub fn execute_script<'a, 'a1, 'f, CM>(
     ce: &'a mut CM,
     script: &'a1 str,
@@ -91,13 +624,36 @@ pub fn execute_script<'a, 'a1, 'f, CM>(
 where
     'a: 'f,
     'a1: 'f,
-    CM: AsClassManager,
+    CM: AsClassManager + AsClassManagerHolder,
 {
     Box::pin(async move {
-        let inc_v = inc::inc_v_from_str(script)?;
+        let inc_v = parse(ce, script)?;
 
         log::debug!("{:?}", inc_v);
 
         execute(ce, &inc_v).await
     })
 }
+
+/// Runs `script` as `file`, so that its statements are traced as lines of `file`.
+pub fn execute_file<'a, 'a1, 'a2, 'f, CM>(
+    ce: &'a mut CM,
+    file: &'a1 str,
+    script: &'a2 str,
+) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+where
+    'a: 'f,
+    'a1: 'f,
+    'a2: 'f,
+    CM: AsClassManager + AsClassManagerHolder,
+{
+    Box::pin(async move {
+        ce.trace_v_mut().push(trace::Location::new(file));
+
+        let rs = execute_script(ce, script).await;
+
+        ce.trace_v_mut().pop();
+
+        rs
+    })
+}
@@ -0,0 +1,51 @@
+/// Undo log of a global manager without transactions, kept by the executor instead.
+///
+/// Every level holds the lists `class(source)` had before its first write, rolling back
+/// sets them again. Shared with the executors of hooks, whose writes belong to the level.
+#[derive(Debug, Default)]
+pub struct Journal {
+    level_v: Vec<Vec<(String, String, Vec<String>)>>,
+}
+
+impl Journal {
+    pub fn is_active(&self) -> bool {
+        !self.level_v.is_empty()
+    }
+
+    pub fn begin(&mut self) {
+        self.level_v.push(vec![]);
+    }
+
+    /// `true` if the innermost level has yet to record `class(source)`.
+    pub fn needs(&self, class: &str, source: &str) -> bool {
+        self.level_v
+            .last()
+            .is_some_and(|level| !level.iter().any(|(c, s, _)| c == class && s == source))
+    }
+
+    pub fn record(&mut self, class: &str, source: &str, target_v: Vec<String>) {
+        if let Some(level) = self.level_v.last_mut() {
+            level.push((class.to_string(), source.to_string(), target_v));
+        }
+    }
+
+    /// Hands the lists of the innermost level to the outer one, which may still undo them.
+    pub fn commit(&mut self) {
+        let Some(level) = self.level_v.pop() else {
+            return;
+        };
+
+        if let Some(outer) = self.level_v.last_mut() {
+            for (class, source, target_v) in level {
+                if !outer.iter().any(|(c, s, _)| *c == class && *s == source) {
+                    outer.push((class, source, target_v));
+                }
+            }
+        }
+    }
+
+    /// Lists to set again to undo the innermost level, `None` if there is no level.
+    pub fn rollback(&mut self) -> Option<Vec<(String, String, Vec<String>)>> {
+        self.level_v.pop()
+    }
+}
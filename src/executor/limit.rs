@@ -0,0 +1,100 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use error_stack::ResultExt;
+use tokio::time::{Duration, Instant};
+
+use crate::err;
+
+/// Budgets of one execution, `None` means unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Limit {
+    /// Max statements executed, nested scripts included.
+    pub max_step: Option<u64>,
+    /// Max iterations of a single `#loop`.
+    pub max_loop: Option<u64>,
+    /// Wall-clock time of the execution.
+    pub timeout: Option<Duration>,
+}
+
+/// Handle for the host to stop a running script.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// Consumption of a [`Limit`] by one execution, shared with nested executors.
+#[derive(Debug, Default)]
+pub struct Budget {
+    limit: Limit,
+    cancel: CancelToken,
+    step: AtomicU64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    pub fn new(limit: Limit, cancel: CancelToken) -> Self {
+        let deadline = limit.timeout.map(|timeout| Instant::now() + timeout);
+
+        Self {
+            limit,
+            cancel,
+            step: AtomicU64::new(0),
+            deadline,
+        }
+    }
+
+    /// Called before every statement.
+    pub fn step(&self) -> err::Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(err::Error::Cancelled).attach_printable("execution cancelled by host");
+        }
+
+        let step = self.step.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(max_step) = self.limit.max_step {
+            if step > max_step {
+                return Err(err::Error::LimitExceeded)
+                    .attach_printable_lazy(|| format!("more than {max_step} statements executed"));
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(err::Error::Timeout).attach_printable_lazy(|| {
+                    format!("execution exceeded {:?}", self.limit.timeout.unwrap())
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Called before every iteration of a `#loop`, `iteration` starts from 1.
+    pub fn iterate(&self, iteration: u64) -> err::Result<()> {
+        if let Some(max_loop) = self.limit.max_loop {
+            if iteration > max_loop {
+                return Err(err::Error::LimitExceeded)
+                    .attach_printable_lazy(|| format!("#loop ran more than {max_loop} times"));
+            }
+        }
+
+        Ok(())
+    }
+}
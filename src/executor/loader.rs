@@ -0,0 +1,307 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+
+use error_stack::ResultExt;
+
+use crate::{
+    def::{AsSendSyncOption, Fu},
+    err,
+};
+
+/// Source of the scripts read by `#include`.
+pub trait ScriptLoader: AsSendSyncOption {
+    /// Canonical key of the script at `path`, `None` if there is no such script.
+    fn resolve(&self, path: &str) -> err::Result<Option<String>>;
+
+    fn load<'a, 'a1, 'f>(
+        &'a self,
+        key: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f;
+}
+
+/// `a/./b/../c` -> `a/c`, `None` if the path climbs above its root.
+pub fn normalize(path: &str) -> Option<String> {
+    let mut part_v: Vec<&str> = vec![];
+
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                part_v.pop()?;
+            }
+            _ => part_v.push(part),
+        }
+    }
+
+    let rs = part_v.join("/");
+
+    if path.starts_with('/') {
+        Some(format!("/{rs}"))
+    } else {
+        Some(rs)
+    }
+}
+
+/// Reads scripts from the file system, optionally confined to a root directory.
+#[derive(Debug, Default)]
+pub struct FileLoader {
+    root: Option<PathBuf>,
+}
+
+impl FileLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only files below `root` can be read, keys are relative to it.
+    ///
+    /// Absolute paths, paths leaving `root` and symlinks are rejected with
+    /// [`err::Error::PermissionDenied`].
+    pub fn sandboxed(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Some(root.into()),
+        }
+    }
+
+    fn resolve_sandboxed(&self, root: &Path, path: &str) -> err::Result<Option<String>> {
+        if Path::new(path).has_root() {
+            return Err(err::Error::PermissionDenied)
+                .attach_printable_lazy(|| format!("resolve: {path} is absolute"));
+        }
+
+        let key = normalize(path)
+            .ok_or(err::Error::PermissionDenied)
+            .attach_printable_lazy(|| format!("resolve: {path} leaves the sandbox"))?;
+
+        let root = fs::canonicalize(root)
+            .change_context(err::Error::NotFound)
+            .attach_printable_lazy(|| format!("resolve: sandbox {}", root.display()))?;
+        let mut file = root.clone();
+
+        for part in key.split('/') {
+            file.push(part);
+
+            match fs::symlink_metadata(&file) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    return Err(err::Error::PermissionDenied)
+                        .attach_printable_lazy(|| format!("resolve: {path} is a symlink"));
+                }
+                Ok(_) => {}
+                Err(_) => return Ok(None),
+            }
+        }
+
+        if !file.is_file() {
+            return Ok(None);
+        }
+
+        canonical_in(&root, &key)?;
+
+        Ok(Some(key))
+    }
+}
+
+/// Canonical path of `key` below the canonical `root`, denied if it leads out of `root`.
+fn canonical_in(root: &Path, key: &str) -> err::Result<PathBuf> {
+    let file = fs::canonicalize(root.join(key))
+        .change_context(err::Error::NotFound)
+        .attach_printable_lazy(|| format!("sandbox: {key}"))?;
+
+    if !file.starts_with(root) {
+        return Err(err::Error::PermissionDenied)
+            .attach_printable_lazy(|| format!("sandbox: {key} leaves the sandbox"));
+    }
+
+    Ok(file)
+}
+
+impl ScriptLoader for FileLoader {
+    fn resolve(&self, path: &str) -> err::Result<Option<String>> {
+        if let Some(root) = &self.root {
+            return self.resolve_sandboxed(root, path);
+        }
+
+        let file = Path::new(path);
+
+        if !file.is_file() {
+            return Ok(None);
+        }
+
+        let file = fs::canonicalize(file)
+            .change_context(err::Error::NotFound)
+            .attach_printable_lazy(|| format!("resolve: {path}"))?;
+
+        Ok(Some(file.to_string_lossy().to_string()))
+    }
+
+    fn load<'a, 'a1, 'f>(
+        &'a self,
+        key: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            // The file may have been swapped since it was resolved, read where it really is.
+            let file = match &self.root {
+                Some(root) => {
+                    let root = fs::canonicalize(root)
+                        .change_context(err::Error::NotFound)
+                        .attach_printable_lazy(|| format!("load: sandbox {}", root.display()))?;
+
+                    canonical_in(&root, key)?
+                }
+                None => PathBuf::from(key),
+            };
+
+            fs::read_to_string(file)
+                .change_context(err::Error::NotFound)
+                .attach_printable_lazy(|| format!("load: {key}"))
+        })
+    }
+}
+
+/// Another loader confined to relative paths that do not climb out of its root.
+pub struct Confined {
+    inner: Arc<dyn ScriptLoader>,
+}
+
+impl Confined {
+    pub fn new(inner: Arc<dyn ScriptLoader>) -> Self {
+        Self { inner }
+    }
+}
+
+impl ScriptLoader for Confined {
+    fn resolve(&self, path: &str) -> err::Result<Option<String>> {
+        if Path::new(path).has_root() || normalize(path).is_none() {
+            return Err(err::Error::PermissionDenied)
+                .attach_printable_lazy(|| format!("resolve: {path} leaves the sandbox"));
+        }
+
+        self.inner.resolve(path)
+    }
+
+    fn load<'a, 'a1, 'f>(
+        &'a self,
+        key: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        self.inner.load(key)
+    }
+}
+
+/// Scripts kept in memory, keyed by normalized path.
+#[derive(Debug, Default)]
+pub struct MemoryLoader {
+    script_mp: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: &str, script: impl Into<String>) {
+        if let Some(key) = normalize(path) {
+            self.script_mp.insert(key, script.into());
+        }
+    }
+}
+
+impl ScriptLoader for MemoryLoader {
+    fn resolve(&self, path: &str) -> err::Result<Option<String>> {
+        Ok(normalize(path).filter(|key| self.script_mp.contains_key(key)))
+    }
+
+    fn load<'a, 'a1, 'f>(
+        &'a self,
+        key: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            self.script_mp
+                .get(key)
+                .cloned()
+                .ok_or(err::Error::NotFound)
+                .attach_printable_lazy(|| format!("load: {key}"))
+        })
+    }
+}
+
+/// Scripts compiled into the binary, see [`crate::script_bundle`].
+#[derive(Debug, Clone, Copy)]
+pub struct BundleLoader {
+    script_v: &'static [(&'static str, &'static str)],
+}
+
+impl BundleLoader {
+    pub const fn new(script_v: &'static [(&'static str, &'static str)]) -> Self {
+        Self { script_v }
+    }
+
+    fn find(&self, key: &str) -> Option<&'static str> {
+        self.script_v
+            .iter()
+            .find(|(path, _)| normalize(path).as_deref() == Some(key))
+            .map(|(_, script)| *script)
+    }
+}
+
+impl ScriptLoader for BundleLoader {
+    fn resolve(&self, path: &str) -> err::Result<Option<String>> {
+        Ok(normalize(path).filter(|key| self.find(key).is_some()))
+    }
+
+    fn load<'a, 'a1, 'f>(
+        &'a self,
+        key: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            self.find(key)
+                .map(|script| script.to_string())
+                .ok_or(err::Error::NotFound)
+                .attach_printable_lazy(|| format!("load: {key}"))
+        })
+    }
+}
+
+/// Bundles scripts relative to the crate root with `include_str!`.
+///
+/// ```
+/// use moon_class::{
+///     executor::loader::{BundleLoader, ScriptLoader},
+///     script_bundle,
+/// };
+///
+/// const BUNDLE: BundleLoader = script_bundle!["assets/class/planet.class"];
+///
+/// assert!(BUNDLE.resolve("assets/class/planet.class").unwrap().is_some());
+/// ```
+#[macro_export]
+macro_rules! script_bundle {
+    ($($path:literal),* $(,)?) => {
+        $crate::executor::loader::BundleLoader::new(&[
+            $(($path, include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path)))),*
+        ])
+    };
+}
@@ -0,0 +1,129 @@
+use error_stack::ResultExt;
+
+use crate::err;
+
+/// Whether `class` is a builtin: a `#` class, or an arithmetic operator, which has no '#'.
+pub fn is_builtin(class: &str) -> bool {
+    class.starts_with('#') || ["+", "-", "*", "/", "%"].contains(&class)
+}
+
+/// What scripts of an executor may do, everything is allowed by default.
+///
+/// `$` locals are always accessible, the policy only guards builtins and global classes.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    builtin_v: Option<Vec<String>>,
+    read_prefix_v: Option<Vec<String>>,
+    write_prefix_v: Option<Vec<String>>,
+    include: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            builtin_v: None,
+            read_prefix_v: None,
+            write_prefix_v: None,
+            include: true,
+        }
+    }
+}
+
+impl Policy {
+    /// Allows nothing, grant capabilities with the `allow_*` methods.
+    pub fn deny_all() -> Self {
+        Self {
+            builtin_v: Some(vec![]),
+            read_prefix_v: Some(vec![]),
+            write_prefix_v: Some(vec![]),
+            include: false,
+        }
+    }
+
+    /// `name` is a `#` class such as `#if`, or an arithmetic operator such as `+`.
+    pub fn allow_builtin(mut self, name: &str) -> Self {
+        if let Some(builtin_v) = &mut self.builtin_v {
+            builtin_v.push(name.to_string());
+        }
+        self
+    }
+
+    pub fn allow_read(mut self, prefix: &str) -> Self {
+        if let Some(read_prefix_v) = &mut self.read_prefix_v {
+            read_prefix_v.push(prefix.to_string());
+        }
+        self
+    }
+
+    /// Writable classes are readable as well.
+    pub fn allow_write(mut self, prefix: &str) -> Self {
+        if let Some(write_prefix_v) = &mut self.write_prefix_v {
+            write_prefix_v.push(prefix.to_string());
+        }
+        self.allow_read(prefix)
+    }
+
+    pub fn allow_include(mut self, include: bool) -> Self {
+        self.include = include;
+        self
+    }
+
+    pub fn check_read(&self, class: &str) -> err::Result<()> {
+        self.check(class, &self.read_prefix_v, "reading")
+    }
+
+    pub fn check_write(&self, class: &str) -> err::Result<()> {
+        self.check(class, &self.write_prefix_v, "writing")
+    }
+
+    /// `name` is a builtin, see [`is_builtin`].
+    pub fn check_builtin(&self, name: &str) -> err::Result<()> {
+        let is_allowed = self
+            .builtin_v
+            .as_ref()
+            .is_none_or(|builtin_v| builtin_v.iter().any(|builtin| builtin == name));
+
+        if is_allowed {
+            Ok(())
+        } else {
+            Err(err::Error::PermissionDenied)
+                .attach_printable_lazy(|| format!("policy: calling {name} is not allowed"))
+        }
+    }
+
+    /// Whether some global classes can not be written.
+    pub fn restricts_write(&self) -> bool {
+        self.write_prefix_v.is_some()
+    }
+
+    pub fn check_include(&self) -> err::Result<()> {
+        if self.include {
+            Ok(())
+        } else {
+            Err(err::Error::PermissionDenied).attach_printable("policy: #include is not allowed")
+        }
+    }
+
+    fn check(&self, class: &str, prefix_v: &Option<Vec<String>>, action: &str) -> err::Result<()> {
+        if class.trim_start_matches('^').starts_with('$') {
+            return Ok(());
+        }
+
+        if is_builtin(class) {
+            return self.check_builtin(class);
+        }
+
+        let is_allowed = prefix_v.as_ref().is_none_or(|prefix_v| {
+            prefix_v
+                .iter()
+                .any(|prefix| class.starts_with(prefix.as_str()))
+        });
+
+        if is_allowed {
+            Ok(())
+        } else {
+            Err(err::Error::PermissionDenied)
+                .attach_printable_lazy(|| format!("policy: {action} {class} is not allowed"))
+        }
+    }
+}
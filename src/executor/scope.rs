@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+/// Locals of one nested script, that is `$` classes of the empty source.
+///
+/// Objects, whose sources are never empty, stay in the temp manager shared by all frames.
+#[derive(Debug, Default)]
+pub struct Frame {
+    class_mp: HashMap<String, Vec<String>>,
+}
+
+impl Frame {
+    pub fn get(&self, class: &str) -> Option<&Vec<String>> {
+        self.class_mp.get(class)
+    }
+
+    pub fn append(&mut self, class: &str, target_v: Vec<String>) {
+        self.class_mp
+            .entry(class.to_string())
+            .or_default()
+            .extend(target_v);
+    }
+
+    /// Targets of all locals.
+    pub fn target_v(&self) -> impl Iterator<Item = &String> {
+        self.class_mp.values().flatten()
+    }
+
+    pub fn remove(&mut self, class: &str, target_v: &[String]) {
+        if let Some(v) = self.class_mp.get_mut(class) {
+            v.retain(|target| !target_v.contains(target));
+        }
+    }
+}
+
+/// `^^$x` -> `(2, "$x")`, `None` if the class is not a temp class.
+pub fn split_depth(class: &str) -> Option<(usize, &str)> {
+    let bare = class.trim_start_matches('^');
+
+    if bare.starts_with('$') {
+        Some((class.len() - bare.len(), bare))
+    } else {
+        None
+    }
+}
+
+/// Finds a local visible `depth` frames out, falling back to outer frames.
+pub fn lookup<'a>(frame_v: &'a [Frame], depth: usize, class: &str) -> Option<&'a Vec<String>> {
+    if depth >= frame_v.len() {
+        return None;
+    }
+
+    frame_v[..frame_v.len() - depth]
+        .iter()
+        .rev()
+        .find_map(|frame| frame.get(class))
+}
+
+/// The frame `depth` frames out, `None` means the root temp manager.
+pub fn frame_mut(frame_v: &mut [Frame], depth: usize) -> Option<&mut Frame> {
+    if depth >= frame_v.len() {
+        return None;
+    }
+
+    let i = frame_v.len() - 1 - depth;
+
+    frame_v.get_mut(i)
+}
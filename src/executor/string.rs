@@ -26,6 +26,38 @@ pub fn find_pat_ignoring_string(pat: &str, s: &str) -> err::Result<Option<usize>
     Ok(None)
 }
 
+/// Like [`find_pat_ignoring_string`], also skipping objects, lists and sources, as statements
+/// are scanned.
+pub fn find_pat_ignoring_group(pat: &str, s: &str) -> err::Result<Option<usize>> {
+    let mut pos = 0;
+
+    while pos < s.len() {
+        let group = [("{", "}"), ("[", "]"), ("(", ")"), ("<", ">")]
+            .into_iter()
+            .find(|(left, _)| s[pos..].starts_with(left));
+
+        if s[pos..].starts_with(pat) {
+            return Ok(Some(pos));
+        } else if s[pos..].starts_with('\"') {
+            pos += 1 + find_string_end(&s[pos + 1..])
+                .ok_or(err::Error::SyntaxError)
+                .attach_printable_lazy(|| {
+                    format!("{}: expected '\"', but not found!", &s[pos..])
+                })?;
+        } else if let Some((left, right)) = group {
+            pos += 1 + find_angle_end(&s[pos + 1..], left, right)?
+                .ok_or(err::Error::SyntaxError)
+                .attach_printable_lazy(|| {
+                    format!("{}: expected '{right}', but not found!", &s[pos..])
+                })?;
+        }
+
+        pos += 1;
+    }
+
+    Ok(None)
+}
+
 /// ''
 pub fn find_string_end(s: &str) -> Option<usize> {
     let mut pos = 0;
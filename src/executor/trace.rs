@@ -0,0 +1,40 @@
+use std::fmt::Display;
+
+use error_stack::Report;
+
+use crate::err;
+
+/// Where a statement is: a `.class` file, or the hook or procedure running it, and a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+}
+
+impl Location {
+    pub fn new(file: impl Into<String>) -> Self {
+        Self {
+            file: file.into(),
+            line: 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at {}:{}", self.file, self.line)
+    }
+}
+
+/// Locations attached to `report` while unwinding, innermost first.
+pub fn trace_of(report: &Report<err::Error>) -> Vec<Location> {
+    let mut location_v = report
+        .frames()
+        .filter_map(|frame| frame.downcast_ref::<Location>())
+        .cloned()
+        .collect::<Vec<Location>>();
+
+    location_v.reverse();
+
+    location_v
+}
@@ -8,8 +8,10 @@ use crate::{
 };
 
 use super::{
+    def::AsClassManagerHolder,
     inc,
-    inner::unwrap_value,
+    inner::{check_data, declare_object, unwrap_value},
+    scope,
     string::{find_angle_end, find_string_end},
 };
 
@@ -20,7 +22,7 @@ pub fn script<'a, 'a1, 'f, CM>(
 where
     'a: 'f,
     'a1: 'f,
-    CM: AsClassManager,
+    CM: AsClassManager + AsClassManagerHolder,
 {
     Box::pin(async move {
         let mut pos = 0;
@@ -67,7 +69,7 @@ pub fn object<'a, 'a1, 'f, CM>(
 where
     'a: 'f,
     'a1: 'f,
-    CM: AsClassManager,
+    CM: AsClassManager + AsClassManagerHolder,
 {
     Box::pin(async move {
         if s.starts_with('[') {
@@ -135,14 +137,19 @@ where
         } else if s.ends_with('}') {
             let (mut pos, root) = if s.starts_with('@') {
                 let pos = s.find('{').unwrap() + 1;
-                let root = s[1..pos - 1].to_string();
+                let root = match &s[1..pos - 1] {
+                    // Not minted by the parser, as in the fields of another object.
+                    "" => ce.id_generator().next_id(),
+                    root => root.to_string(),
+                };
 
                 log::debug!("unwrap_value: root = {root}");
 
                 (pos, root)
             } else {
-                (1, uuid::Uuid::new_v4().to_string())
+                (1, ce.id_generator().next_id())
             };
+            let mut is_global = false;
             let mut entry_v = vec![];
             let mut start = pos;
 
@@ -233,6 +240,10 @@ where
                 let value_v =
                     unwrap_value(ce, &inc::IncVal::from_str(entry[pos + 1..].trim())?).await?;
 
+                check_data(ce, key.first().unwrap()).await?;
+
+                is_global |= scope::split_depth(key.first().unwrap()).is_none();
+
                 if s.starts_with('@') {
                     ce.remove(
                         key.first().unwrap(),
@@ -245,6 +256,9 @@ where
                 ce.append(key.first().unwrap(), &root, value_v).await?;
             }
 
+            // Declared once it has its fields, a collection before keeps it as a named source.
+            declare_object(ce, &root, is_global).await?;
+
             Ok(vec![root])
         } else {
             Err(err::Error::SyntaxError).attach_printable_lazy(|| format!("{s} not a object!"))
@@ -1,13 +1,17 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeSet, HashMap, HashSet},
     pin::Pin,
 };
 
+use error_stack::ResultExt;
+
 mod bean;
 
+pub mod buffered;
 pub mod def;
 pub mod err;
 pub mod executor;
+pub mod schema;
 pub mod util;
 
 pub struct ClassManager {
@@ -16,6 +20,35 @@ pub struct ClassManager {
     class_source_inx: HashMap<(String, String), BTreeSet<u64>>,
     target_class_inx: HashMap<(String, String), BTreeSet<u64>>,
     source_inx: HashMap<String, BTreeSet<u64>>,
+    target_inx: HashMap<String, BTreeSet<u64>>,
+    journal_v: Vec<Vec<Undo>>,
+    set_class_set: HashSet<String>,
+    is_all_set: bool,
+    /// Sources declared objects, the only ones a collection deletes.
+    object_set: HashSet<String>,
+}
+
+/// How to undo one change of a transaction.
+enum Undo {
+    Append(u64),
+    Remove(u64, bean::Item),
+}
+
+/// Changes of a [`ClassManager`] in order, undone by [`ClassManager::undo`].
+#[derive(Default)]
+pub struct UndoLog(Vec<Undo>);
+
+impl UndoLog {
+    /// Appends the changes of `log`, made after those of this one.
+    pub fn extend(&mut self, log: UndoLog) {
+        self.0.extend(log.0);
+    }
+}
+
+impl Default for ClassManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ClassManager {
@@ -26,16 +59,74 @@ impl ClassManager {
             class_source_inx: HashMap::new(),
             target_class_inx: HashMap::new(),
             source_inx: HashMap::new(),
+            target_inx: HashMap::new(),
+            journal_v: vec![],
+            set_class_set: HashSet::new(),
+            is_all_set: false,
+            object_set: HashSet::new(),
         }
     }
 
+    /// Makes `class` a set, appending a target it already has is ignored.
+    pub fn mark_set(&mut self, class: &str) {
+        self.set_class_set.insert(class.to_string());
+    }
+
+    /// Makes every class a set.
+    pub fn mark_all_sets(&mut self) {
+        self.is_all_set = true;
+    }
+
+    pub fn is_set(&self, class: &str) -> bool {
+        self.is_all_set || self.set_class_set.contains(class)
+    }
+
+    fn contains(&self, class: &str, source: &str, target: &str) -> bool {
+        self.target_class_inx
+            .get(&(target.to_string(), class.to_string()))
+            .is_some_and(|set| {
+                set.iter()
+                    .any(|id| self.class_mp.get(id).unwrap().source == source)
+            })
+    }
+
+    /// Drops the targets a set already has, or that come twice.
+    fn new_target_v(&self, class: &str, source: &str, target_v: Vec<String>) -> Vec<String> {
+        if !self.is_set(class) {
+            return target_v;
+        }
+
+        let mut rs: Vec<String> = vec![];
+
+        for target in target_v {
+            if !rs.contains(&target) && !self.contains(class, source, &target) {
+                rs.push(target);
+            }
+        }
+
+        rs
+    }
+
+    /// Fields of `source` in order, the step of every traversal of objects.
+    fn field_v(&self, source: &str) -> impl Iterator<Item = &bean::Item> {
+        self.source_inx
+            .get(source)
+            .into_iter()
+            .flatten()
+            .map(|id| self.class_mp.get(id).unwrap())
+    }
+
+    fn is_object(&self, source: &str) -> bool {
+        self.source_inx
+            .get(source)
+            .is_some_and(|set| !set.is_empty())
+    }
+
     pub fn dump(&self, source: &str) -> json::JsonValue {
-        if let Some(set) = self.source_inx.get(source) {
+        if self.is_object(source) {
             let mut obj = json::object! {};
 
-            for id in set {
-                let item = self.class_mp.get(id).unwrap();
-
+            for item in self.field_v(source) {
                 log::debug!("dump: {source}->{}: {}", item.class, item.target);
 
                 if let json::JsonValue::Array(vec) = &mut obj[&item.class] {
@@ -54,29 +145,404 @@ impl ClassManager {
     pub fn get_source(&self, target: &str, class: &str) -> Option<Vec<String>> {
         let target_class_k = (target.to_string(), class.to_string());
 
-        match self.target_class_inx.get(&target_class_k) {
-            Some(set) => Some(
-                set.iter()
-                    .map(|id| self.class_mp.get(id).unwrap().source.clone())
-                    .collect(),
-            ),
-            None => None,
-        }
+        self.target_class_inx.get(&target_class_k).map(|set| {
+            set.iter()
+                .map(|id| self.class_mp.get(id).unwrap().source.clone())
+                .collect()
+        })
     }
 
     pub fn get_target(&self, class: &str, source: &str) -> Option<Vec<String>> {
         let class_source_k = (class.to_string(), source.to_string());
 
-        if let Some(set) = self.class_source_inx.get(&class_source_k) {
-            Some(
-                set.iter()
-                    .map(|id| self.class_mp.get(id).unwrap().target.clone())
-                    .collect(),
-            )
-        } else {
-            None
+        self.class_source_inx.get(&class_source_k).map(|set| {
+            set.iter()
+                .map(|id| self.class_mp.get(id).unwrap().target.clone())
+                .collect()
+        })
+    }
+
+    /// A copy of the edges and sets, without the transactions in progress.
+    /// Starts to log the changes, up to [`ClassManager::end_log`].
+    pub fn begin_log(&mut self) {
+        self.journal_v.push(vec![]);
+    }
+
+    /// Changes since the matching [`ClassManager::begin_log`].
+    pub fn end_log(&mut self) -> UndoLog {
+        UndoLog(self.journal_v.pop().unwrap_or_default())
+    }
+
+    /// Undoes the changes of `log`, later changes of the same lists stay.
+    pub fn undo(&mut self, log: UndoLog) {
+        for undo in log.0.into_iter().rev() {
+            match undo {
+                Undo::Append(id) => {
+                    self.remove_item(id);
+                }
+                Undo::Remove(id, item) => self.insert_item(id, item),
+            }
         }
     }
+
+    /// Edges matching `pattern`, looked up by the most specific index.
+    pub fn query_edge_v(&self, pattern: &def::Pattern) -> Vec<def::Edge> {
+        let set = match (&pattern.class, &pattern.source, &pattern.target) {
+            (Some(class), Some(source), _) => {
+                self.class_source_inx.get(&(class.clone(), source.clone()))
+            }
+            (Some(class), None, Some(target)) => {
+                self.target_class_inx.get(&(target.clone(), class.clone()))
+            }
+            (None, Some(source), _) => self.source_inx.get(source),
+            (None, None, Some(target)) => self.target_inx.get(target),
+            (_, None, None) => None,
+        };
+
+        let id_v = match set {
+            Some(set) => set.iter().copied().collect(),
+            None if pattern.source.is_none() && pattern.target.is_none() => {
+                let mut id_v = self.class_mp.keys().copied().collect::<Vec<u64>>();
+
+                id_v.sort();
+
+                id_v
+            }
+            None => vec![],
+        };
+
+        id_v.into_iter()
+            .map(|id| self.class_mp.get(&id).unwrap())
+            .filter(|item| pattern.matches(&item.class, &item.source, &item.target))
+            .map(|item| (item.class.clone(), item.source.clone(), item.target.clone()))
+            .collect()
+    }
+
+    /// Edges below `source` as `class[i].class[j]: target`, ids of objects replaced by `{}`.
+    ///
+    /// Sorted, so that the order of classes does not matter but the order in a class does.
+    pub fn edge_v(&self, source: &str) -> Vec<String> {
+        let mut edge_v = vec![];
+
+        self.collect_edge_v(source, "", &mut HashSet::new(), &mut edge_v);
+
+        edge_v.sort();
+
+        edge_v
+    }
+
+    /// Whether `left` and `right` are objects with equal edges, or equal values.
+    pub fn is_equal(&self, left: &str, right: &str) -> bool {
+        match (self.is_object(left), self.is_object(right)) {
+            (true, true) => self.edge_v(left) == self.edge_v(right),
+            (false, false) => left == right,
+            _ => false,
+        }
+    }
+
+    fn collect_edge_v(
+        &self,
+        source: &str,
+        prefix: &str,
+        visited: &mut HashSet<String>,
+        edge_v: &mut Vec<String>,
+    ) {
+        // Shared and cyclic objects are followed once.
+        if !visited.insert(source.to_string()) {
+            return;
+        }
+
+        let mut index_mp: HashMap<&str, usize> = HashMap::new();
+
+        for item in self.field_v(source) {
+            let index = index_mp.entry(&item.class).or_default();
+            let path = format!("{prefix}{}[{index}]", item.class);
+
+            *index += 1;
+
+            if self.is_object(&item.target) {
+                edge_v.push(format!("{path}: {{}}"));
+
+                self.collect_edge_v(&item.target, &format!("{path}."), visited, edge_v);
+            } else {
+                edge_v.push(format!("{path}: {}", item.target));
+            }
+        }
+    }
+
+    /// Copies the objects reachable from `source`, giving the copies ids from `next_id`.
+    ///
+    /// Shared and cyclic objects are copied once, a source without fields is its own copy.
+    pub fn clone_object(&mut self, source: &str, next_id: &mut dyn FnMut() -> String) -> String {
+        if !self.is_object(source) {
+            return source.to_string();
+        }
+
+        let mut id_mp = HashMap::from([(source.to_string(), next_id())]);
+        let mut copy_v = vec![];
+        let mut pending_v = vec![source.to_string()];
+
+        while let Some(old) = pending_v.pop() {
+            let new = id_mp.get(&old).unwrap().clone();
+            let item_v = self
+                .field_v(&old)
+                .map(|item| (item.class.clone(), item.target.clone()))
+                .collect::<Vec<(String, String)>>();
+
+            for (class, target) in item_v {
+                let target = if !self.is_object(&target) {
+                    target
+                } else if let Some(copy) = id_mp.get(&target) {
+                    copy.clone()
+                } else {
+                    let copy = next_id();
+
+                    id_mp.insert(target.clone(), copy.clone());
+                    pending_v.push(target);
+
+                    copy
+                };
+
+                self.append_item(&class, &new, target);
+            }
+
+            copy_v.push(new);
+        }
+
+        self.object_set.extend(copy_v);
+
+        id_mp.remove(source).unwrap()
+    }
+
+    /// References `(class, source, target)` to objects without fields, deleted ones included.
+    ///
+    /// An empty object can not be told from a deleted one, so references to it are reported too.
+    pub fn dangling_v(&self) -> Vec<def::Edge> {
+        let mut id_v = self
+            .class_mp
+            .iter()
+            .filter(|(_, item)| {
+                util::is_object_id(&item.target)
+                    && self
+                        .source_inx
+                        .get(&item.target)
+                        .is_none_or(|set| set.is_empty())
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<u64>>();
+
+        id_v.sort();
+
+        id_v.into_iter()
+            .map(|id| {
+                let item = self.class_mp.get(&id).unwrap();
+
+                (item.class.clone(), item.source.clone(), item.target.clone())
+            })
+            .collect()
+    }
+
+    fn mark_from(&self, root_v: Vec<String>) -> Vec<String> {
+        let mut live_set = HashSet::new();
+        let mut pending_v = root_v;
+
+        pending_v.extend(
+            self.source_inx
+                .iter()
+                .filter(|(source, set)| !set.is_empty() && !self.object_set.contains(*source))
+                .map(|(source, _)| source.clone()),
+        );
+
+        while let Some(source) = pending_v.pop() {
+            if live_set.contains(&source) {
+                continue;
+            }
+
+            pending_v.extend(self.field_v(&source).map(|item| item.target.clone()));
+
+            live_set.insert(source);
+        }
+
+        live_set.into_iter().collect()
+    }
+
+    fn sweep_unmarked(&mut self, live_v: Vec<String>) -> Vec<String> {
+        let live_set = live_v.into_iter().collect::<HashSet<String>>();
+
+        let mut garbage_v = self
+            .object_set
+            .iter()
+            .filter(|source| !live_set.contains(*source) && self.is_object(source))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        garbage_v.sort();
+
+        self.object_set.retain(|source| live_set.contains(source));
+
+        for source in &garbage_v {
+            let id_v = self.source_inx.remove(source).unwrap_or_default();
+
+            for id in id_v {
+                if let Some(item) = self.remove_item(id) {
+                    if let Some(journal) = self.journal_v.last_mut() {
+                        journal.push(Undo::Remove(id, item));
+                    }
+                }
+            }
+        }
+
+        garbage_v
+    }
+
+    fn delete_object(&mut self, source: &str, is_cascade: bool) -> Vec<String> {
+        let mut deleted_v = vec![];
+        let mut pending_v = vec![source.to_string()];
+
+        while let Some(source) = pending_v.pop() {
+            if deleted_v.contains(&source) {
+                continue;
+            }
+
+            let id_v = self
+                .source_inx
+                .get(&source)
+                .into_iter()
+                .chain(self.target_inx.get(&source))
+                .flatten()
+                .copied()
+                .collect::<BTreeSet<u64>>();
+            let mut child_v = vec![];
+
+            for id in id_v {
+                if let Some(item) = self.remove_item(id) {
+                    if item.source == source {
+                        child_v.push(item.target.clone());
+                    }
+
+                    if let Some(journal) = self.journal_v.last_mut() {
+                        journal.push(Undo::Remove(id, item));
+                    }
+                }
+            }
+
+            if is_cascade {
+                // Owned exclusively: an object no one references any more.
+                pending_v.extend(child_v.into_iter().filter(|child| {
+                    self.source_inx
+                        .get(child)
+                        .is_some_and(|set| !set.is_empty())
+                        && self.target_inx.get(child).is_none_or(|set| set.is_empty())
+                }));
+            }
+
+            self.object_set.remove(&source);
+
+            deleted_v.push(source);
+        }
+
+        deleted_v
+    }
+
+    /// Rewrites the targets of `class(source)` from the position `edit` returns.
+    ///
+    /// Targets before it keep their ids, the rewritten ones get new ids to keep the order.
+    fn edit_tail<F>(&mut self, class: &str, source: &str, edit: F) -> err::Result<()>
+    where
+        F: FnOnce(Vec<String>) -> err::Result<(usize, Vec<String>)>,
+    {
+        let id_v = self
+            .class_source_inx
+            .get(&(class.to_string(), source.to_string()))
+            .map(|set| set.iter().copied().collect::<Vec<u64>>())
+            .unwrap_or_default();
+        let target_v = id_v
+            .iter()
+            .map(|id| self.class_mp.get(id).unwrap().target.clone())
+            .collect();
+
+        let (start, tail_v) = edit(target_v)?;
+
+        for id in &id_v[start..] {
+            if let Some(item) = self.remove_item(*id) {
+                if let Some(journal) = self.journal_v.last_mut() {
+                    journal.push(Undo::Remove(*id, item));
+                }
+            }
+        }
+
+        let tail_v = self.new_target_v(class, source, tail_v);
+
+        for target in tail_v {
+            self.append_item(class, source, target);
+        }
+
+        Ok(())
+    }
+
+    fn append_item(&mut self, class: &str, source: &str, target: String) {
+        let id = self.unique_id;
+
+        self.unique_id += 1;
+
+        self.insert_item(
+            id,
+            bean::Item {
+                class: class.to_string(),
+                source: source.to_string(),
+                target,
+            },
+        );
+
+        if let Some(journal) = self.journal_v.last_mut() {
+            journal.push(Undo::Append(id));
+        }
+    }
+
+    fn insert_item(&mut self, id: u64, item: bean::Item) {
+        self.class_source_inx
+            .entry((item.class.clone(), item.source.clone()))
+            .or_default()
+            .insert(id);
+        self.target_class_inx
+            .entry((item.target.clone(), item.class.clone()))
+            .or_default()
+            .insert(id);
+        self.source_inx
+            .entry(item.source.clone())
+            .or_default()
+            .insert(id);
+        self.target_inx
+            .entry(item.target.clone())
+            .or_default()
+            .insert(id);
+        self.class_mp.insert(id, item);
+    }
+
+    fn remove_item(&mut self, id: u64) -> Option<bean::Item> {
+        let item = self.class_mp.remove(&id)?;
+
+        if let Some(set) = self
+            .class_source_inx
+            .get_mut(&(item.class.clone(), item.source.clone()))
+        {
+            set.remove(&id);
+        }
+        if let Some(set) = self
+            .target_class_inx
+            .get_mut(&(item.target.clone(), item.class.clone()))
+        {
+            set.remove(&id);
+        }
+        if let Some(set) = self.source_inx.get_mut(&item.source) {
+            set.remove(&id);
+        }
+        if let Some(set) = self.target_inx.get_mut(&item.target) {
+            set.remove(&id);
+        }
+
+        Some(item)
+    }
 }
 
 impl def::AsClassManager for ClassManager {
@@ -98,11 +564,11 @@ impl def::AsClassManager for ClassManager {
 
             let class_source_k = (class.to_string(), source.to_string());
 
-            if let Some(set) = self.class_source_inx.get_mut(&class_source_k) {
+            if let Some(set) = self.class_source_inx.get(&class_source_k) {
                 let id_v = set
                     .iter()
                     .filter(|id| {
-                        if let Some(item_class) = self.class_mp.get(&id) {
+                        if let Some(item_class) = self.class_mp.get(id) {
                             if target_set.contains(&item_class.target) {
                                 return true;
                             }
@@ -110,21 +576,13 @@ impl def::AsClassManager for ClassManager {
 
                         false
                     })
-                    .map(|id| *id)
+                    .copied()
                     .collect::<Vec<u64>>();
 
-                for id in &id_v {
-                    set.remove(id);
-
-                    if let Some(item_class) = self.class_mp.remove(&id) {
-                        if let Some(set) = self
-                            .target_class_inx
-                            .get_mut(&(item_class.target, class.to_string()))
-                        {
-                            set.remove(&id);
-                        }
-                        if let Some(set) = self.source_inx.get_mut(&item_class.source) {
-                            set.remove(&id);
+                for id in id_v {
+                    if let Some(item) = self.remove_item(id) {
+                        if let Some(journal) = self.journal_v.last_mut() {
+                            journal.push(Undo::Remove(id, item));
                         }
                     }
                 }
@@ -146,61 +604,275 @@ impl def::AsClassManager for ClassManager {
         'a2: 'f,
     {
         Box::pin(async move {
-            let mut id = self.unique_id;
+            let target_v = self.new_target_v(class, source, target_v);
+            let first_id = self.unique_id;
 
             self.unique_id += target_v.len() as u64;
 
-            for target in &target_v {
-                self.class_mp.insert(
+            for (id, target) in (first_id..).zip(target_v) {
+                self.insert_item(
                     id,
                     bean::Item {
                         class: class.to_string(),
                         source: source.to_string(),
-                        target: target.clone(),
+                        target,
                     },
                 );
 
-                let class_pair_k = (class.to_string(), source.to_string());
+                if let Some(journal) = self.journal_v.last_mut() {
+                    journal.push(Undo::Append(id));
+                }
+            }
+
+            Ok(())
+        })
+    }
 
-                if let Some(set) = self.class_source_inx.get_mut(&class_pair_k) {
-                    set.insert(id);
-                } else {
-                    let mut set = BTreeSet::new();
+    fn compare_and_set<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        expected_v: Vec<String>,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<bool>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let current_v = self.get_target(class, source).unwrap_or_default();
 
-                    set.insert(id);
+            if current_v != expected_v {
+                return Ok(false);
+            }
 
-                    self.class_source_inx.insert(class_pair_k, set);
-                }
+            self.remove(class, source, current_v).await?;
+            self.append(class, source, target_v).await?;
 
-                let target_class_k = (target.clone(), class.to_string());
+            Ok(true)
+        })
+    }
 
-                if let Some(set) = self.target_class_inx.get_mut(&target_class_k) {
-                    set.insert(id);
-                } else {
-                    let mut set = BTreeSet::new();
+    fn insert<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let target_v = self.new_target_v(class, source, target_v);
 
-                    set.insert(id);
+            self.edit_tail(class, source, |mut list| {
+                util::insert_at(&mut list, index, target_v)?;
 
-                    self.target_class_inx.insert(target_class_k, set);
-                }
+                Ok((index, list.split_off(index)))
+            })
+        })
+    }
 
-                if let Some(set) = self.source_inx.get_mut(source) {
-                    set.insert(id);
-                } else {
-                    let mut set = BTreeSet::new();
+    fn remove_at<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        index: usize,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<String>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let mut target = String::new();
+
+            self.edit_tail(class, source, |mut list| {
+                target = util::remove_at(&mut list, index)?;
+
+                Ok((index, list.split_off(index)))
+            })?;
+
+            Ok(target)
+        })
+    }
 
-                    set.insert(id);
+    fn move_to<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        from: usize,
+        to: usize,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            self.edit_tail(class, source, |mut list| {
+                util::move_to(&mut list, from, to)?;
 
-                    self.source_inx.insert(source.to_string(), set);
+                let start = from.min(to);
+
+                Ok((start, list.split_off(start)))
+            })
+        })
+    }
+
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move { Ok(self.delete_object(source, is_cascade)) })
+    }
+
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(
+            async move { Ok(ClassManager::get_source(self, target, class).unwrap_or_default()) },
+        )
+    }
+
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<def::Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            Ok(util::bfs(start, |node| {
+                if is_reverse {
+                    ClassManager::get_source(self, node, class).unwrap_or_default()
+                } else {
+                    self.get_target(class, node).unwrap_or_default()
                 }
+            }))
+        })
+    }
+
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: def::Pattern,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<def::Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { Ok(self.query_edge_v(&pattern)) })
+    }
 
-                id += 1;
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        Box::pin(async move {
+            self.object_set.insert(source.to_string());
+
+            Ok(())
+        })
+    }
+
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { Ok(self.object_set.iter().cloned().collect()) })
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { Ok(self.mark_from(root_v)) })
+    }
+
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn def::Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move { Ok(self.sweep_unmarked(live_v)) })
+    }
+
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            self.journal_v.push(vec![]);
+
+            Ok(())
+        })
+    }
+
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let journal = self
+                .journal_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("commit: no transaction")?;
+
+            // A nested transaction is undone with the one holding it.
+            if let Some(outer) = self.journal_v.last_mut() {
+                outer.extend(journal);
             }
 
             Ok(())
         })
     }
 
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn def::Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        Box::pin(async move {
+            let journal = self
+                .journal_v
+                .pop()
+                .ok_or(err::Error::RuntimeError)
+                .attach_printable("rollback: no transaction")?;
+
+            self.undo(UndoLog(journal));
+
+            Ok(())
+        })
+    }
+
     fn get<'a, 'a1, 'a2, 'f>(
         &'a self,
         class: &'a1 str,
@@ -211,20 +883,6 @@ impl def::AsClassManager for ClassManager {
         'a1: 'f,
         'a2: 'f,
     {
-        Box::pin(async move {
-            match class {
-                "#source" => {
-                    let data = json::parse(source).unwrap();
-
-                    Ok(self
-                        .get_source(
-                            data["$target"][0].as_str().unwrap(),
-                            data["$class"][0].as_str().unwrap(),
-                        )
-                        .unwrap_or_default())
-                }
-                _ => Ok(self.get_target(class, source).unwrap_or_default()),
-            }
-        })
+        Box::pin(async move { Ok(self.get_target(class, source).unwrap_or_default()) })
     }
 }
@@ -0,0 +1,324 @@
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+
+use error_stack::ResultExt;
+
+use crate::{
+    def::{AsClassManager, Edge, Fu, Pattern, Step},
+    err,
+    executor::inc,
+    util,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cardinality {
+    One,
+    Many,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Any,
+    Number,
+    String,
+    /// Id of an object made by an object literal.
+    Object,
+    /// Text parsing as statements.
+    Script,
+}
+
+impl ValueType {
+    pub fn accept(&self, target: &str) -> bool {
+        match self {
+            ValueType::Any | ValueType::String => true,
+            ValueType::Number => target.parse::<f64>().is_ok(),
+            ValueType::Object => util::is_object_id(target),
+            ValueType::Script => inc::inc_v_from_str(target).is_ok(),
+        }
+    }
+}
+
+/// Declaration of a class.
+#[derive(Debug, Clone)]
+pub struct ClassDef {
+    pub cardinality: Cardinality,
+    pub value_type: ValueType,
+    /// A required class can not lose its last target, checked by the executor.
+    pub is_required: bool,
+}
+
+impl ClassDef {
+    pub fn one(value_type: ValueType) -> Self {
+        Self {
+            cardinality: Cardinality::One,
+            value_type,
+            is_required: false,
+        }
+    }
+
+    pub fn many(value_type: ValueType) -> Self {
+        Self {
+            cardinality: Cardinality::Many,
+            value_type,
+            is_required: false,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.is_required = true;
+        self
+    }
+}
+
+/// Declared classes, undeclared ones take anything.
+#[derive(Debug, Clone, Default)]
+pub struct Schema {
+    class_mp: HashMap<String, ClassDef>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn declare(mut self, class: &str, class_def: ClassDef) -> Self {
+        self.class_mp.insert(class.to_string(), class_def);
+        self
+    }
+
+    pub fn get(&self, class: &str) -> Option<&ClassDef> {
+        self.class_mp.get(class)
+    }
+
+    /// Checks appending `target_v` to `class(source)` which holds `count` targets.
+    pub fn check_append(
+        &self,
+        class: &str,
+        source: &str,
+        count: usize,
+        target_v: &[String],
+    ) -> err::Result<()> {
+        let class_def = match self.get(class) {
+            Some(class_def) => class_def,
+            None => return Ok(()),
+        };
+
+        if let Some(target) = target_v
+            .iter()
+            .find(|target| !class_def.value_type.accept(target))
+        {
+            return Err(err::Error::TypeMismatch).attach_printable_lazy(|| {
+                format!(
+                    "{class}({source}): '{target}' is not {:?}",
+                    class_def.value_type
+                )
+            });
+        }
+
+        if class_def.cardinality == Cardinality::One && count + target_v.len() > 1 {
+            return Err(err::Error::CardinalityViolated).attach_printable_lazy(|| {
+                format!(
+                    "{class}({source}): holds one target, {} given",
+                    count + target_v.len()
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `class(source)` may be left with `count` targets.
+    pub fn check_remain(&self, class: &str, source: &str, count: usize) -> err::Result<()> {
+        match self.get(class) {
+            Some(class_def) if class_def.is_required && count == 0 => {
+                Err(err::Error::MissingRequired)
+                    .attach_printable_lazy(|| format!("{class}({source}): is required"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Validates appends to a manager against a [`Schema`].
+pub struct Validated<CM> {
+    inner: CM,
+    schema: Arc<Schema>,
+}
+
+impl<CM> Validated<CM> {
+    pub fn new(inner: CM, schema: Schema) -> Self {
+        Self {
+            inner,
+            schema: Arc::new(schema),
+        }
+    }
+
+    pub fn inner(&self) -> &CM {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> CM {
+        self.inner
+    }
+}
+
+impl<CM: AsClassManager> AsClassManager for Validated<CM> {
+    fn get<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        source: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.inner.get(class, source)
+    }
+
+    fn remove<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.inner.remove(class, source, target_v)
+    }
+
+    fn append<'a, 'a1, 'a2, 'f>(
+        &'a mut self,
+        class: &'a1 str,
+        source: &'a2 str,
+        target_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        Box::pin(async move {
+            let count = match self.schema.get(class) {
+                Some(class_def) if class_def.cardinality == Cardinality::One => {
+                    self.inner.get(class, source).await?.len()
+                }
+                _ => 0,
+            };
+
+            self.schema.check_append(class, source, count, &target_v)?;
+
+            self.inner.append(class, source, target_v).await
+        })
+    }
+
+    fn delete<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+        is_cascade: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        self.inner.delete(source, is_cascade)
+    }
+
+    fn get_source<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        target: &'a1 str,
+        class: &'a2 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.inner.get_source(target, class)
+    }
+
+    fn traverse<'a, 'a1, 'a2, 'f>(
+        &'a self,
+        class: &'a1 str,
+        start: &'a2 str,
+        is_reverse: bool,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Step>>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+        'a2: 'f,
+    {
+        self.inner.traverse(class, start, is_reverse)
+    }
+
+    fn query<'a, 'f>(
+        &'a self,
+        pattern: Pattern,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<Edge>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.query(pattern)
+    }
+
+    fn declare_object<'a, 'a1, 'f>(
+        &'a mut self,
+        source: &'a1 str,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+        'a1: 'f,
+    {
+        self.inner.declare_object(source)
+    }
+
+    fn object_v<'a, 'f>(&'a self) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.object_v()
+    }
+
+    fn mark<'a, 'f>(
+        &'a self,
+        root_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.mark(root_v)
+    }
+
+    fn sweep<'a, 'f>(
+        &'a mut self,
+        live_v: Vec<String>,
+    ) -> Pin<Box<dyn Fu<Output = err::Result<Vec<String>>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.sweep(live_v)
+    }
+
+    fn begin<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.begin()
+    }
+
+    fn commit<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.commit()
+    }
+
+    fn rollback<'a, 'f>(&'a mut self) -> Pin<Box<dyn Fu<Output = err::Result<()>> + 'f>>
+    where
+        'a: 'f,
+    {
+        self.inner.rollback()
+    }
+}
@@ -1,3 +1,9 @@
+use std::collections::{HashMap, HashSet};
+
+use error_stack::ResultExt;
+
+use crate::{def, err};
+
 pub fn str_of_value(word: &str) -> String {
     let content = word
         .replace("\\", "\\\\")
@@ -52,9 +58,7 @@ pub fn rs_2_str(rs: &[String]) -> String {
         return acc;
     }
 
-    for i in 0..rs.len() - 1 {
-        let item = &rs[i];
-
+    for item in &rs[..rs.len() - 1] {
         acc = if item.ends_with("\\c") {
             format!("{acc}{}", &item[..item.len() - 2])
         } else {
@@ -100,3 +104,157 @@ pub fn str_2_rs(s: &str) -> Vec<String> {
 
     rs
 }
+
+/// Inserts `target_v` before `index`, `index` may be the length to append.
+pub fn insert_at(list: &mut Vec<String>, index: usize, target_v: Vec<String>) -> err::Result<()> {
+    if index > list.len() {
+        return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+            format!("insert at {index}, but there are {} targets", list.len())
+        });
+    }
+
+    list.splice(index..index, target_v);
+
+    Ok(())
+}
+
+pub fn remove_at(list: &mut Vec<String>, index: usize) -> err::Result<String> {
+    if index >= list.len() {
+        return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+            format!("remove at {index}, but there are {} targets", list.len())
+        });
+    }
+
+    Ok(list.remove(index))
+}
+
+/// Moves the target at `from` so that it ends up at `to`.
+pub fn move_to(list: &mut Vec<String>, from: usize, to: usize) -> err::Result<()> {
+    if from >= list.len() || to >= list.len() {
+        return Err(err::Error::RuntimeError).attach_printable_lazy(|| {
+            format!("move {from} to {to}, but there are {} targets", list.len())
+        });
+    }
+
+    let target = list.remove(from);
+
+    list.insert(to, target);
+
+    Ok(())
+}
+
+/// Whether `target` looks like the id of an object made by an object literal.
+///
+/// A host may name sources the same way, collections go by
+/// [`crate::def::AsClassManager::declare_object`] instead.
+pub fn is_object_id(target: &str) -> bool {
+    uuid::Uuid::parse_str(target).is_ok()
+}
+
+/// Breadth-first search from `start`, `next_v` giving the neighbours of a node.
+///
+/// Every node is reached once, `start` only if it is on a cycle.
+pub fn bfs(start: &str, mut next_v: impl FnMut(&str) -> Vec<String>) -> Vec<def::Step> {
+    let mut bfs = Bfs::new(start);
+
+    while let Some(node) = bfs.node() {
+        let node_next_v = next_v(node);
+
+        bfs.expand(node_next_v);
+    }
+
+    bfs.into_step_v()
+}
+
+/// [`bfs`] one node at a time, for neighbours that have to be awaited.
+pub struct Bfs {
+    step_v: Vec<def::Step>,
+    visited: HashSet<String>,
+    node: Option<String>,
+    distance: usize,
+    /// Steps are the queue, `pos` is the next one to expand.
+    pos: usize,
+}
+
+impl Bfs {
+    pub fn new(start: &str) -> Self {
+        Self {
+            step_v: vec![],
+            visited: HashSet::new(),
+            node: Some(start.to_string()),
+            distance: 0,
+            pos: 0,
+        }
+    }
+
+    /// Node whose neighbours come next, `None` once the search is over.
+    pub fn node(&self) -> Option<&str> {
+        self.node.as_deref()
+    }
+
+    /// [`Bfs::node`] and the nodes queued after it at its distance, to expand in this order.
+    pub fn frontier(&self) -> Vec<String> {
+        self.node
+            .iter()
+            .cloned()
+            .chain(
+                self.step_v[self.pos..]
+                    .iter()
+                    .take_while(|step| step.distance == self.distance)
+                    .map(|step| step.node.clone()),
+            )
+            .collect()
+    }
+
+    /// Takes the neighbours of [`Bfs::node`] and moves on to the next node.
+    pub fn expand(&mut self, next_v: Vec<String>) {
+        let Some(node) = self.node.take() else {
+            return;
+        };
+
+        for next in next_v {
+            if self.visited.insert(next.clone()) {
+                self.step_v.push(def::Step {
+                    node: next,
+                    parent: node.clone(),
+                    distance: self.distance + 1,
+                });
+            }
+        }
+
+        if let Some(step) = self.step_v.get(self.pos) {
+            self.node = Some(step.node.clone());
+            self.distance = step.distance;
+            self.pos += 1;
+        }
+    }
+
+    pub fn into_step_v(self) -> Vec<def::Step> {
+        self.step_v
+    }
+}
+
+/// Nodes from `from` to `to` along the parents of `step_v`, empty if `to` was not reached.
+pub fn path_of(step_v: &[def::Step], from: &str, to: &str) -> Vec<String> {
+    let parent_mp = step_v
+        .iter()
+        .map(|step| (step.node.as_str(), step.parent.as_str()))
+        .collect::<HashMap<&str, &str>>();
+
+    let mut path = vec![to.to_string()];
+    let mut node = to;
+
+    while node != from {
+        match parent_mp.get(node) {
+            Some(parent) => {
+                path.push(parent.to_string());
+                node = parent;
+            }
+            None => return vec![],
+        }
+    }
+
+    path.reverse();
+
+    path
+}